@@ -0,0 +1,164 @@
+use std::io;
+use std::time::Duration;
+
+use crate::commander::MigrationError;
+
+/// The result type returned by midas_core
+pub type Result<T, E = MidasError> = std::result::Result<T, E>;
+
+/// A boxed error raised by a database driver
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The errors returned by midas_core
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum MidasError {
+  /// A migration filename or its contents could not be parsed
+  #[error("Failed to parse {filename}: {reason}")]
+  Parse { filename: String, reason: String },
+
+  /// A migration file lacks its `-- !UP` or `-- !DOWN` marker
+  #[error("Parser can't find the {marker} migration in {filename}")]
+  MissingMarker { filename: String, marker: &'static str },
+
+  /// The database URL could not be understood
+  #[error("Invalid database URL: {0}")]
+  InvalidUrl(String),
+
+  /// The connection to the database could not be opened
+  #[error("Failed to connect to the database")]
+  Connection(#[source] BoxError),
+
+  /// The TLS settings of the connection could not be loaded
+  #[error("Failed to set up TLS for the database connection")]
+  Tls(#[source] BoxError),
+
+  /// A query issued by the driver failed
+  #[error("{context}")]
+  Driver {
+    context: String,
+    #[source]
+    source: BoxError,
+  },
+
+  /// A migration failed to execute, pointing back into its source file
+  #[error(transparent)]
+  Migration(Box<MigrationError>),
+
+  /// Applied migrations were edited after they were applied
+  #[error(
+    "Migration file(s) changed since they were applied: {}. Restore the original contents or create a new \
+     migration instead",
+    filenames.join(", ")
+  )]
+  ChecksumMismatch { filenames: Vec<String> },
+
+  /// Applied migrations are missing on disk, listed by the versions of their filenames
+  #[error(
+    "Migration(s) applied to the database but missing on disk: {}. Restore the migration files or ignore \
+     missing migrations to run anyway",
+    versions.join(", ")
+  )]
+  OrphanedMigrations { versions: Vec<String> },
+
+  /// A migration in the plan has no migration file
+  #[error("Migration file not found for migration {0}")]
+  MissingMigration(i64),
+
+  /// Another migrator held the migration lock for longer than the timeout
+  #[error(
+    "Another migrator holds the lock on {resource}, gave up after waiting {}s",
+    timeout.as_secs()
+  )]
+  LockTimeout { resource: String, timeout: Duration },
+
+  /// No template with the given name exists in the templates directory or among the built-ins
+  #[error(
+    "Template {0} was not found in the templates directory, the built-in templates are create_table, \
+     add_column and create_index"
+  )]
+  UnknownTemplate(String),
+
+  /// A migration body uses a `${NAME}` placeholder without a value
+  #[error("Variable ${{{name}}} used in {filename} at line {line} is not defined")]
+  UndefinedVariable {
+    name: String,
+    filename: String,
+    line: usize,
+  },
+
+  /// A table or schema name that can't be used unquoted in SQL
+  #[error("Invalid identifier {0}, use letters, digits and underscores not starting with a digit")]
+  InvalidIdentifier(String),
+
+  /// The migrations table and the migration files disagree
+  #[error("{0}")]
+  State(String),
+
+  /// A file or directory could not be read or written
+  #[error("{context}")]
+  Io {
+    context: String,
+    #[source]
+    source: io::Error,
+  },
+}
+
+impl MidasError {
+  /// Wrap a filesystem error with what was being done
+  pub(crate) fn io(context: impl Into<String>, source: io::Error) -> Self {
+    Self::Io {
+      context: context.into(),
+      source,
+    }
+  }
+
+  /// Wrap a driver error with what was being done
+  pub(crate) fn driver(context: impl Into<String>, source: impl Into<BoxError>) -> Self {
+    Self::Driver {
+      context: context.into(),
+      source: source.into(),
+    }
+  }
+}
+
+impl From<MigrationError> for MidasError {
+  fn from(err: MigrationError) -> Self {
+    Self::Migration(Box::new(err))
+  }
+}
+
+impl From<io::Error> for MidasError {
+  fn from(err: io::Error) -> Self {
+    Self::io("Filesystem operation failed", err)
+  }
+}
+
+impl From<postgres::Error> for MidasError {
+  fn from(err: postgres::Error) -> Self {
+    Self::driver("Postgres query failed", err)
+  }
+}
+
+impl From<mysql::Error> for MidasError {
+  fn from(err: mysql::Error) -> Self {
+    Self::driver("MySQL query failed", err)
+  }
+}
+
+impl From<rusqlite::Error> for MidasError {
+  fn from(err: rusqlite::Error) -> Self {
+    Self::driver("SQLite query failed", err)
+  }
+}
+
+/// Attach the failed operation to a driver error
+pub(crate) trait DriverContext<T> {
+  fn context(self, context: &str) -> Result<T>;
+}
+
+impl<T, E: Into<BoxError>> DriverContext<T> for std::result::Result<T, E> {
+  fn context(self, context: &str) -> Result<T> {
+    self.map_err(|err| MidasError::driver(context, err))
+  }
+}
@@ -1,35 +1,31 @@
 pub mod commander;
+pub mod error;
+pub mod linter;
 pub mod lookup;
+pub mod report;
 pub mod sequel;
+pub mod splitter;
+pub mod template;
+pub mod variables;
 
 use std::fs;
 use std::path::Path;
 
-use anyhow::{
-  Context as _,
-  Result as AnyhowResult,
+use crate::error::{
+  MidasError,
+  Result as MidasResult,
 };
 
-use indicatif::ProgressStyle;
-
 /// Ensure the migration state directory exists
-pub fn ensure_migration_state_dir_exists() -> AnyhowResult<()> {
+pub fn ensure_migration_state_dir_exists() -> MidasResult<()> {
   let migration_dir = Path::new(".migrations-state");
 
   // If the directory does not exist, create it
   // Otherwise, do nothing
   if !migration_dir.exists() {
-    fs::create_dir_all(migration_dir).context("Failed to create migrations directory")?;
+    fs::create_dir_all(migration_dir)
+      .map_err(|err| MidasError::io("Failed to create migrations directory", err))?;
   }
 
   Ok(())
 }
-
-/// Setup the progress style
-pub fn progress_style() -> AnyhowResult<ProgressStyle> {
-  let style = ProgressStyle::default_bar()
-    .template("{spinner:.green} [{prefix:.bold.dim}] {wide_msg:.cyan/blue} ")?
-    .tick_chars("⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏⦿");
-
-  Ok(style)
-}
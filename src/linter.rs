@@ -0,0 +1,360 @@
+//! Static safety checks for risky statements in migrations
+//!
+//! Every statement of the UP section is matched against a set of rules for the
+//! target dialect. A rule is skipped for a whole file with a `-- midas:allow <rule>` line.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::lookup::{
+  MigrationFile,
+  MigrationFiles,
+};
+use crate::splitter::{
+  split_statements,
+  Dialect,
+};
+
+/// How risky a flagged statement is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+  /// Worth a second look
+  Warning,
+  /// Likely to lock or lose data in production
+  Error,
+}
+
+impl fmt::Display for Severity {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Warning => write!(f, "warning"),
+      Self::Error => write!(f, "error"),
+    }
+  }
+}
+
+impl FromStr for Severity {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "warning" => Ok(Self::Warning),
+      "error" => Ok(Self::Error),
+      _ => Err(format!("Unknown severity: {s}")),
+    }
+  }
+}
+
+/// A check for a risky statement pattern
+pub struct Rule {
+  /// The name used in findings and `-- midas:allow` directives
+  pub name: &'static str,
+  /// How risky the flagged statements are
+  pub severity: Severity,
+  /// The dialects the rule applies to
+  pub dialects: &'static [Dialect],
+  /// Why the flagged statements are risky
+  pub message: &'static str,
+  /// Whether the normalized words of a statement match the rule
+  check: fn(&[&str]) -> bool,
+}
+
+const ALL_DIALECTS: &[Dialect] = &[Dialect::Postgres, Dialect::Mysql, Dialect::Sqlite];
+
+/// Every rule of the linter
+pub const RULES: &[Rule] = &[
+  Rule {
+    name: "create-index-non-concurrent",
+    severity: Severity::Error,
+    dialects: &[Dialect::Postgres],
+    message: "CREATE INDEX without CONCURRENTLY blocks writes to the table while the index builds",
+    check: create_index_non_concurrent,
+  },
+  Rule {
+    name: "add-column-not-null-without-default",
+    severity: Severity::Error,
+    dialects: &[Dialect::Postgres, Dialect::Mysql],
+    message: "Adding a NOT NULL column without a DEFAULT fails on tables with rows or rewrites them",
+    check: add_column_not_null_without_default,
+  },
+  Rule {
+    name: "change-column-type",
+    severity: Severity::Warning,
+    dialects: &[Dialect::Postgres, Dialect::Mysql],
+    message: "Changing a column type usually rewrites the table while holding an exclusive lock",
+    check: change_column_type,
+  },
+  Rule {
+    name: "drop-column",
+    severity: Severity::Warning,
+    dialects: ALL_DIALECTS,
+    message: "Dropping a column breaks code still reading it and loses its data",
+    check: drop_column,
+  },
+  Rule {
+    name: "drop-table",
+    severity: Severity::Warning,
+    dialects: ALL_DIALECTS,
+    message: "Dropping a table breaks code still reading it and loses its data",
+    check: drop_table,
+  },
+  Rule {
+    name: "update-without-where",
+    severity: Severity::Error,
+    dialects: ALL_DIALECTS,
+    message: "UPDATE without WHERE changes every row of the table",
+    check: update_without_where,
+  },
+  Rule {
+    name: "delete-without-where",
+    severity: Severity::Error,
+    dialects: ALL_DIALECTS,
+    message: "DELETE without WHERE removes every row of the table",
+    check: delete_without_where,
+  },
+];
+
+/// A risky statement found in a migration file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+  /// The name of the rule that flagged the statement
+  pub rule: &'static str,
+  /// How risky the statement is
+  pub severity: Severity,
+  /// The migration filename
+  pub filename: String,
+  /// The line of the migration file the statement starts at, starting at 1
+  pub line: usize,
+  /// Why the statement is risky
+  pub message: &'static str,
+}
+
+fn starts_with(words: &[&str], prefix: &[&str]) -> bool {
+  words.starts_with(prefix)
+}
+
+fn contains(words: &[&str], word: &str) -> bool {
+  words.contains(&word)
+}
+
+fn create_index_non_concurrent(words: &[&str]) -> bool {
+  (starts_with(words, &["CREATE", "INDEX"]) || starts_with(words, &["CREATE", "UNIQUE", "INDEX"]))
+    && !contains(words, "CONCURRENTLY")
+}
+
+fn add_column_not_null_without_default(words: &[&str]) -> bool {
+  starts_with(words, &["ALTER", "TABLE"])
+    && contains(words, "ADD")
+    && words.windows(2).any(|w| w == ["NOT", "NULL"])
+    && !contains(words, "DEFAULT")
+}
+
+fn change_column_type(words: &[&str]) -> bool {
+  if !starts_with(words, &["ALTER", "TABLE"]) {
+    return false;
+  }
+
+  // Postgres: ALTER [COLUMN] name [SET DATA] TYPE, MySQL: MODIFY or CHANGE [COLUMN]
+  let alter_type = words
+    .iter()
+    .enumerate()
+    .skip(2)
+    .filter(|(_, word)| **word == "ALTER")
+    .any(|(i, _)| words[i + 1..].iter().take(5).any(|word| *word == "TYPE"));
+  alter_type || contains(words, "MODIFY") || contains(words, "CHANGE")
+}
+
+fn drop_column(words: &[&str]) -> bool {
+  // Dropping anything else than a column is allowed
+  const NOT_COLUMNS: &[&str] = &[
+    "CONSTRAINT",
+    "DEFAULT",
+    "NOT",
+    "INDEX",
+    "KEY",
+    "PRIMARY",
+    "FOREIGN",
+    "CHECK",
+    "IDENTITY",
+    "EXPRESSION",
+    "PARTITION",
+  ];
+
+  starts_with(words, &["ALTER", "TABLE"])
+    && words
+      .windows(2)
+      .any(|w| w[0] == "DROP" && (w[1] == "COLUMN" || !NOT_COLUMNS.contains(&w[1])))
+}
+
+fn drop_table(words: &[&str]) -> bool {
+  starts_with(words, &["DROP", "TABLE"])
+}
+
+fn update_without_where(words: &[&str]) -> bool {
+  starts_with(words, &["UPDATE"]) && !contains(words, "WHERE")
+}
+
+fn delete_without_where(words: &[&str]) -> bool {
+  starts_with(words, &["DELETE"]) && !contains(words, "WHERE")
+}
+
+/// Strip comments and literals, then uppercase the statement and split it into words
+fn normalize(sql: &str) -> String {
+  let mut normalized = String::with_capacity(sql.len());
+  let mut chars = sql.chars().peekable();
+  while let Some(c) = chars.next() {
+    match c {
+      '-' if chars.peek() == Some(&'-') => {
+        for c in chars.by_ref() {
+          if c == '\n' {
+            break;
+          }
+        }
+        normalized.push(' ');
+      },
+      '/' if chars.peek() == Some(&'*') => {
+        chars.next();
+        let mut prev = ' ';
+        for c in chars.by_ref() {
+          if prev == '*' && c == '/' {
+            break;
+          }
+          prev = c;
+        }
+        normalized.push(' ');
+      },
+      // Literals could contain keywords, only keep their quotes
+      '\'' => {
+        for c in chars.by_ref() {
+          if c == '\'' {
+            break;
+          }
+        }
+        normalized.push_str(" '' ");
+      },
+      '(' | ')' | ',' | ';' => {
+        normalized.push(' ');
+        normalized.push(c);
+        normalized.push(' ');
+      },
+      _ => normalized.extend(c.to_uppercase()),
+    }
+  }
+  normalized
+}
+
+/// Lint the UP section of a migration file for the given dialect
+pub fn lint_migration(migration: &MigrationFile, dialect: Dialect) -> Vec<Finding> {
+  let Some(content) = &migration.content_up else {
+    return Vec::new();
+  };
+
+  let rules = RULES
+    .iter()
+    .filter(|rule| {
+      rule.dialects.contains(&dialect) && !migration.allowed_rules.iter().any(|allowed| allowed == rule.name)
+    })
+    .collect::<Vec<_>>();
+
+  let mut findings = Vec::new();
+  for statement in split_statements(&content.text, dialect) {
+    let normalized = normalize(&statement.sql);
+    let words = normalized.split_whitespace().collect::<Vec<_>>();
+    for rule in rules.iter().filter(|rule| (rule.check)(&words)) {
+      findings.push(Finding {
+        rule: rule.name,
+        severity: rule.severity,
+        filename: migration.filename.clone(),
+        line: content.line + statement.line - 1,
+        message: rule.message,
+      });
+    }
+  }
+  findings
+}
+
+/// Lint every migration file for the given dialect
+pub fn lint_migrations(migrations: &MigrationFiles, dialect: Dialect) -> Vec<Finding> {
+  migrations
+    .values()
+    .flat_map(|migration| lint_migration(migration, dialect))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::lookup::MigrationContent;
+
+  fn migration(up: &str, allowed_rules: &[&str]) -> MigrationFile {
+    MigrationFile {
+      content_up: Some(MigrationContent {
+        text: up.to_owned(),
+        line: 2,
+        path: None,
+      }),
+      content_down: None,
+      number: 1,
+      filename: "0000000000001_test.sql".to_owned(),
+      path: "migrations/0000000000001_test.sql".into(),
+      no_transaction: false,
+      allowed_rules: allowed_rules.iter().map(|rule| (*rule).to_owned()).collect(),
+      timeouts: Default::default(),
+      checksum: String::new(),
+    }
+  }
+
+  fn rules(up: &str, dialect: Dialect) -> Vec<&'static str> {
+    lint_migration(&migration(up, &[]), dialect)
+      .into_iter()
+      .map(|finding| finding.rule)
+      .collect()
+  }
+
+  #[test]
+  fn it_should_flag_risky_statements() {
+    let up = "create index users_email_idx on users (email);\n\
+              create index concurrently users_name_idx on users (name);\n\
+              alter table users add column age int not null;\n\
+              alter table users add column score int not null default 0;\n\
+              alter table users alter column age type bigint;\n\
+              alter table users drop column name;\n\
+              alter table users drop constraint users_pkey;\n\
+              drop table posts;\n\
+              update users set age = 1;\n\
+              delete from users where id = 1;\n\
+              delete from users;\n\
+              update users set note = 'drop table x' where id = 1; -- delete from users";
+    assert_eq!(
+      rules(up, Dialect::Postgres),
+      vec![
+        "create-index-non-concurrent",
+        "add-column-not-null-without-default",
+        "change-column-type",
+        "drop-column",
+        "drop-table",
+        "update-without-where",
+        "delete-without-where",
+      ]
+    );
+
+    assert_eq!(
+      rules(
+        "CREATE INDEX a_idx ON a (id);\nALTER TABLE a MODIFY COLUMN id BIGINT;",
+        Dialect::Mysql
+      ),
+      vec!["change-column-type"]
+    );
+  }
+
+  #[test]
+  fn it_should_point_at_the_line_and_honour_allow_directives() {
+    let up = "\ncreate table a (id int);\n\ndrop table b;\n";
+    let findings = lint_migration(&migration(up, &[]), Dialect::Sqlite);
+    assert_eq!(findings.len(), 1);
+    assert_eq!(findings[0].line, 5);
+    assert_eq!(findings[0].severity, Severity::Warning);
+
+    assert!(lint_migration(&migration(up, &["drop-table"]), Dialect::Sqlite).is_empty());
+  }
+}
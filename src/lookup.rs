@@ -1,135 +1,974 @@
-use anyhow::{
-  Context,
-  Result as AnyhowResult,
-};
-use indoc::indoc;
+use openssl::sha::sha256;
 use regex::Regex;
 use std::collections::BTreeMap;
+use std::fmt;
 use std::fs::{
   self,
   File,
 };
 use std::io::prelude::*;
 use std::io::BufReader;
-use std::path::Path;
-use std::string::ToString;
+use std::path::{
+  Path,
+  PathBuf,
+};
+use std::str::FromStr;
 use std::time::{
   SystemTime,
   UNIX_EPOCH,
 };
 
-pub type VecStr = Vec<String>;
+use crate::commander::{
+  current_os_user,
+  Direction,
+};
+use crate::error::{
+  MidasError,
+  Result as MidasResult,
+};
+use crate::sequel::Timeouts;
+use crate::template::{
+  render,
+  table_from_slug,
+  Placeholders,
+};
+
+/// Directive that opts a migration file out of the wrapping transaction
+const NO_TRANSACTION_DIRECTIVE: &str = "-- !NO_TRANSACTION";
+
+/// Directive that sets the statement timeout of a migration file
+const TIMEOUT_DIRECTIVE: &str = "-- !TIMEOUT";
+
+/// Directive that sets the lock timeout of a migration file
+const LOCK_TIMEOUT_DIRECTIVE: &str = "-- !LOCK_TIMEOUT";
+
+/// Directive that suppresses linter rules for a migration file
+const ALLOW_DIRECTIVE: &str = "-- midas:allow";
+
+/// The file holding the UP section in the directory layout
+const UP_FILENAME: &str = "up.sql";
+
+/// The file holding the DOWN section in the directory layout
+const DOWN_FILENAME: &str = "down.sql";
+
+/// The filename prefix of repeatable migrations
+const REPEATABLE_PREFIX: &str = "R_";
+
+/// How a migration is laid out on disk
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Layout {
+  /// A `<number>_<name>.sql` file with `-- !UP` and `-- !DOWN` markers
+  #[default]
+  Single,
+  /// A `<number>_<name>.up.sql` and a `<number>_<name>.down.sql` file
+  Paired,
+  /// A `<number>_<name>` directory holding an `up.sql` and a `down.sql` file
+  Directory,
+}
+
+impl FromStr for Layout {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "single" => Ok(Self::Single),
+      "paired" => Ok(Self::Paired),
+      "directory" => Ok(Self::Directory),
+      _ => Err(format!("Unknown migration layout: {s}")),
+    }
+  }
+}
+
+/// How migration versions are written at the start of migration filenames
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VersionFormat {
+  /// Milliseconds since the Unix epoch, 13 digits
+  #[default]
+  Timestamp,
+  /// A UTC `YYYYMMDDHHMMSS` stamp, 14 digits
+  Datetime,
+  /// A zero-padded sequence, one more than the latest migration
+  Sequential,
+}
+
+impl FromStr for VersionFormat {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "timestamp" => Ok(Self::Timestamp),
+      "datetime" => Ok(Self::Datetime),
+      "sequential" => Ok(Self::Sequential),
+      _ => Err(format!("Unknown version format: {s}")),
+    }
+  }
+}
+
+impl VersionFormat {
+  /// The regex matching the version part of migration filenames
+  fn pattern(self) -> &'static str {
+    match self {
+      Self::Timestamp => "[0-9]{13}",
+      Self::Datetime => "[0-9]{14}",
+      Self::Sequential => "[0-9]+",
+    }
+  }
+
+  /// Format a migration number the way it is written in filenames
+  pub fn format(self, number: i64) -> String {
+    match self {
+      Self::Timestamp => format!("{number:013}"),
+      Self::Datetime => format!("{number:014}"),
+      Self::Sequential => format!("{number:04}"),
+    }
+  }
+
+  /// Generate the version of a new migration
+  pub fn next(self, migrations: &MigrationFiles) -> String {
+    match self {
+      Self::Timestamp => {
+        let since_the_epoch = SystemTime::now()
+          .duration_since(UNIX_EPOCH)
+          .expect("Time went backwards");
+        since_the_epoch.as_millis().to_string()
+      },
+      Self::Datetime => humantime::format_rfc3339_seconds(SystemTime::now())
+        .to_string()
+        .chars()
+        .filter(char::is_ascii_digit)
+        .collect(),
+      Self::Sequential => {
+        let latest = migrations.keys().next_back().copied().unwrap_or(0);
+        self.format(latest + 1)
+      },
+    }
+  }
+}
+
+/// A section of a migration file kept exactly as written
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MigrationContent {
+  /// The raw text of the section
+  pub text: String,
+  /// The line of the migration file the section starts at, starting at 1
+  pub line: usize,
+  /// The file holding the section, when it is not the migration file itself
+  pub path: Option<PathBuf>,
+}
 
 #[derive(Debug)]
 pub struct MigrationFile {
-  pub content_up: Option<VecStr>,
-  pub content_down: Option<VecStr>,
+  pub content_up: Option<MigrationContent>,
+  pub content_down: Option<MigrationContent>,
   pub number: i64,
+  /// The migration filename, the UP filename or the directory name depending on the layout
   pub filename: String,
+  /// The path of the migration file, UP file or directory
+  pub path: PathBuf,
+  /// Run the migration outside of a transaction
+  pub no_transaction: bool,
+  /// The linter rules suppressed with `-- midas:allow <rule>` directives
+  pub allowed_rules: Vec<String>,
+  /// The timeouts set with `-- !TIMEOUT` and `-- !LOCK_TIMEOUT` directives
+  pub timeouts: Timeouts,
+  /// The SHA-256 checksum of the file contents
+  pub checksum: String,
 }
 
 impl MigrationFile {
+  /// Get the migration name without the number and extension
+  pub fn name(&self) -> &str {
+    self
+      .filename
+      .split_once('_')
+      .map_or(self.filename.as_str(), |(_, name)| name)
+      .trim_end_matches(".sql")
+      .trim_end_matches(".up")
+  }
+
   fn new(filename: &str, number: i64) -> Self {
     Self {
       content_up: None,
       content_down: None,
       filename: filename.to_owned(),
+      path: PathBuf::new(),
       number,
+      no_transaction: false,
+      allowed_rules: Vec::new(),
+      timeouts: Timeouts::default(),
+      checksum: String::new(),
     }
   }
 }
 
+/// Collect the rules named by the `-- midas:allow <rule>[, <rule>]` directives of a file
+fn allowed_rules(content: &str) -> Vec<String> {
+  content
+    .lines()
+    .filter_map(|line| line.trim().strip_prefix(ALLOW_DIRECTIVE))
+    .flat_map(|rules| rules.split(|c: char| c == ',' || c.is_whitespace()))
+    .filter(|rule| !rule.is_empty())
+    .map(ToOwned::to_owned)
+    .collect()
+}
+
+/// Read the `-- !TIMEOUT <duration>` and `-- !LOCK_TIMEOUT <duration>` directives of a file
+fn timeouts(content: &str) -> Result<Timeouts, String> {
+  let mut timeouts = Timeouts::default();
+  for line in content.lines() {
+    for (directive, timeout) in [
+      (TIMEOUT_DIRECTIVE, &mut timeouts.statement),
+      (LOCK_TIMEOUT_DIRECTIVE, &mut timeouts.lock),
+    ] {
+      let Some(value) = line.trim().strip_prefix(directive) else {
+        continue;
+      };
+      if !value.starts_with(char::is_whitespace) {
+        continue;
+      }
+      let duration = humantime::parse_duration(value.trim())
+        .map_err(|err| format!("Invalid duration in the {directive} directive: {err}"))?;
+      *timeout = Some(duration);
+    }
+  }
+  Ok(timeouts)
+}
+
+/// Read the timeout directives of a migration file, reporting invalid durations as parse errors
+fn parse_timeouts(filename: &str, content: &str) -> MidasResult<Timeouts> {
+  timeouts(content).map_err(|reason| MidasError::Parse {
+    filename: filename.to_owned(),
+    reason,
+  })
+}
+
 /// A map of migration files
 pub type MigrationFiles = BTreeMap<i64, MigrationFile>;
 
+/// A `R_<name>.sql` migration, applied again whenever its checksum changes
+#[derive(Debug)]
+pub struct RepeatableFile {
+  /// The migration name without the prefix and extension
+  pub name: String,
+  pub filename: String,
+  pub path: PathBuf,
+  /// The whole file, repeatable migrations have no markers
+  pub content: MigrationContent,
+  /// Run the migration outside of a transaction
+  pub no_transaction: bool,
+  /// The SHA-256 checksum of the file contents
+  pub checksum: String,
+}
+
+/// A map of repeatable migrations by name, in the order they are applied
+pub type RepeatableFiles = BTreeMap<String, RepeatableFile>;
+
+/// The points of a migration run where a callback script can run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Callback {
+  /// Before the first migration of the run
+  BeforeAll,
+  /// Before each migration, in the same transaction
+  BeforeEach,
+  /// After each migration, in the same transaction
+  AfterEach,
+  /// After the last migration of the run
+  AfterAll,
+  /// After a migration or another callback failed
+  OnError,
+}
+
+impl Callback {
+  /// Every callback, in the order they are looked up
+  pub const ALL: [Self; 5] = [
+    Self::BeforeAll,
+    Self::BeforeEach,
+    Self::AfterEach,
+    Self::AfterAll,
+    Self::OnError,
+  ];
+
+  /// Get the callback name, its filename without the leading underscore and extension
+  pub fn name(self) -> &'static str {
+    match self {
+      Self::BeforeAll => "before_all",
+      Self::BeforeEach => "before_each",
+      Self::AfterEach => "after_each",
+      Self::AfterAll => "after_all",
+      Self::OnError => "on_error",
+    }
+  }
+
+  /// Get the filename of the callback script in the migrations directory
+  pub fn filename(self) -> String {
+    format!("_{}.sql", self.name())
+  }
+
+  /// Find the callback a filename belongs to
+  pub fn from_filename(filename: &str) -> Option<Self> {
+    Self::ALL
+      .into_iter()
+      .find(|callback| callback.filename() == filename)
+  }
+}
+
+/// A `_<callback>.sql` script run at a fixed point of every migration run
+#[derive(Debug)]
+pub struct CallbackFile {
+  pub callback: Callback,
+  pub filename: String,
+  pub path: PathBuf,
+  /// The whole file, callback scripts have no markers
+  pub content: MigrationContent,
+}
+
+/// A map of the callback scripts found in the migrations directory
+pub type CallbackFiles = BTreeMap<Callback, CallbackFile>;
+
+/// Compute the hex encoded SHA-256 checksum of the migration contents
+pub fn checksum(content: &str) -> String {
+  sha256(content.as_bytes())
+    .iter()
+    .map(|b| format!("{b:02x}"))
+    .collect()
+}
+
+/// Find the byte range and line number of a marker line
+fn find_marker(content: &str, marker: &str) -> Option<(usize, usize, usize)> {
+  let mut offset = 0;
+  for (index, line) in content.split_inclusive('\n').enumerate() {
+    let start = offset;
+    offset += line.len();
+    if line.trim_end_matches(['\r', '\n']) == marker {
+      return Some((start, offset, index + 1));
+    }
+  }
+  None
+}
+
+/// Split the migration contents on the `-- !UP` and `-- !DOWN` markers
+/// Both sections keep their text verbatim along with the line they start at
+fn parse_content(filename: &str, content: &str) -> MidasResult<(MigrationContent, MigrationContent)> {
+  let find_marker = |marker| find_marker(content, marker);
+  let missing_marker = |marker| MidasError::MissingMarker {
+    filename: filename.to_owned(),
+    marker,
+  };
+  let (_, up_end, up_line) = find_marker("-- !UP").ok_or_else(|| missing_marker("UP"))?;
+  let (down_start, down_end, down_line) = find_marker("-- !DOWN").ok_or_else(|| missing_marker("DOWN"))?;
+  if down_start < up_end {
+    return Err(MidasError::Parse {
+      filename: filename.to_owned(),
+      reason: "The DOWN migration must come after the UP migration".to_owned(),
+    });
+  }
+
+  let content_up = MigrationContent {
+    text: content[up_end..down_start].to_owned(),
+    line: up_line + 1,
+    path: None,
+  };
+  let content_down = MigrationContent {
+    text: content[down_end..].to_owned(),
+    line: down_line + 1,
+    path: None,
+  };
+
+  Ok((content_up, content_down))
+}
+
 /// Parse the migration file
-fn parse_file(filename: &str) -> AnyhowResult<MigrationFile> {
+fn parse_file(filename: &str, format: VersionFormat) -> MidasResult<MigrationFile> {
+  let invalid = |reason: &str| MidasError::Parse {
+    filename: filename.to_owned(),
+    reason: reason.to_owned(),
+  };
+
   // Regex to parse the migration file
-  let re = Regex::new(r"^(?P<number>[0-9]{13})_(?P<name>[_0-9a-zA-Z]*)\.sql$")?;
+  let re = Regex::new(&format!(
+    r"^(?P<number>{})_(?P<name>[_0-9a-zA-Z]*)\.sql$",
+    format.pattern()
+  ))
+  .expect("valid regex");
 
   // Parse the filename
   let result = re
     .captures(filename)
-    .with_context(|| format!("Invalid filename found on {filename}"))?;
+    .ok_or_else(|| invalid("Invalid migration filename"))?;
 
   // Extract the number
   let number = result
     .name("number")
-    .context("The migration file timestamp is missing")?
+    .ok_or_else(|| invalid("The migration file timestamp is missing"))?
     .as_str()
-    .parse::<i64>()?;
+    .parse::<i64>()
+    .map_err(|err| invalid(&err.to_string()))?;
 
   Ok(MigrationFile::new(filename, number))
 }
 
+/// Parse a `<number>_<name>.up.sql` or `<number>_<name>.down.sql` filename
+/// The migration takes the name of the UP file
+fn parse_paired_file(filename: &str, format: VersionFormat) -> MidasResult<(MigrationFile, Direction)> {
+  let re = Regex::new(&format!(
+    r"^(?P<stem>(?P<number>{})_[_0-9a-zA-Z]*)\.(?P<direction>up|down)\.sql$",
+    format.pattern()
+  ))
+  .expect("valid regex");
+  let result = re.captures(filename).ok_or_else(|| MidasError::Parse {
+    filename: filename.to_owned(),
+    reason: "Invalid migration filename".to_owned(),
+  })?;
+
+  let direction = match &result["direction"] {
+    "up" => Direction::Up,
+    _ => Direction::Down,
+  };
+  let number = result["number"].parse::<i64>().map_err(|err| MidasError::Parse {
+    filename: filename.to_owned(),
+    reason: err.to_string(),
+  })?;
+
+  let up_filename = format!("{}.up.sql", &result["stem"]);
+  Ok((MigrationFile::new(&up_filename, number), direction))
+}
+
+/// Parse a `<number>_<name>` migration directory name
+fn parse_directory(dirname: &str, format: VersionFormat) -> MidasResult<MigrationFile> {
+  let re = Regex::new(&format!(r"^(?P<number>{})_[_0-9a-zA-Z]*$", format.pattern())).expect("valid regex");
+  let invalid = |reason: String| MidasError::Parse {
+    filename: dirname.to_owned(),
+    reason,
+  };
+  let result = re
+    .captures(dirname)
+    .ok_or_else(|| invalid("Invalid migration directory name".to_owned()))?;
+  let number = result["number"]
+    .parse::<i64>()
+    .map_err(|err| invalid(err.to_string()))?;
+
+  Ok(MigrationFile::new(dirname, number))
+}
+
+/// Parse a `R_<name>.sql` filename, returning the name
+fn parse_repeatable_file(filename: &str) -> Option<&str> {
+  filename
+    .strip_prefix(REPEATABLE_PREFIX)?
+    .strip_suffix(".sql")
+    .filter(|name| !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'))
+}
+
+/// Read a migration whose UP and DOWN sections live in separate files
+fn read_split_migration(
+  info: MigrationFile,
+  path: PathBuf,
+  up_path: PathBuf,
+  down_path: PathBuf,
+) -> MidasResult<MigrationFile> {
+  let read = |path: &Path| {
+    fs::read_to_string(path).map_err(|err| MidasError::io(format!("Failed to read {path:?}"), err))
+  };
+  let up = read(&up_path)?;
+  let down = read(&down_path)?;
+
+  let no_transaction = up
+    .lines()
+    .chain(down.lines())
+    .any(|s| s.trim_end() == NO_TRANSACTION_DIRECTIVE);
+  let mut rules = allowed_rules(&up);
+  rules.extend(allowed_rules(&down));
+  let timeouts = parse_timeouts(&info.filename, &up)?.or(parse_timeouts(&info.filename, &down)?);
+
+  Ok(MigrationFile {
+    checksum: checksum(&(up.clone() + &down)),
+    content_up: Some(MigrationContent {
+      text: up,
+      line: 1,
+      path: Some(up_path),
+    }),
+    content_down: Some(MigrationContent {
+      text: down,
+      line: 1,
+      path: Some(down_path),
+    }),
+    no_transaction,
+    allowed_rules: rules,
+    timeouts,
+    path,
+    ..info
+  })
+}
+
+/// A paired migration with the paths of its UP and DOWN files, once seen
+type PairedHalves = (MigrationFile, Option<PathBuf>, Option<PathBuf>);
+
+/// Add a migration to the list, refusing a number already used by another migration
+fn insert_migration(files: &mut MigrationFiles, migration: MigrationFile) -> MidasResult<()> {
+  if let Some(other) = files.get(&migration.number) {
+    return Err(MidasError::Parse {
+      reason: format!("Migration number is also used by {}", other.filename),
+      filename: migration.filename,
+    });
+  }
+
+  files.insert(migration.number, migration);
+  Ok(())
+}
+
 /// Build the migration list
-pub fn build_migration_list(path: &Path) -> AnyhowResult<MigrationFiles> {
+pub fn build_migration_list(path: &Path, format: VersionFormat) -> MidasResult<MigrationFiles> {
   let mut files: MigrationFiles = BTreeMap::new();
-  let entries = fs::read_dir(path)?.filter_map(Result::ok).collect::<Vec<_>>();
+  let mut entries = fs::read_dir(path)
+    .map_err(|err| MidasError::io(format!("Failed to read the migrations directory {path:?}"), err))?
+    .filter_map(Result::ok)
+    .collect::<Vec<_>>();
+  entries.sort_by_key(|entry| entry.file_name());
+
+  // The halves of paired migrations by number and UP filename, merged once every entry was seen
+  let mut paired: BTreeMap<(i64, String), PairedHalves> = BTreeMap::new();
 
   for entry in entries {
     let filename = entry.file_name();
-    let Ok(info) = parse_file(filename.to_str().context("Filename is not valid")?) else {
+    let filename = filename.to_str().ok_or_else(|| MidasError::Parse {
+      filename: filename.to_string_lossy().into_owned(),
+      reason: "Filename is not valid".to_owned(),
+    })?;
+
+    if entry.path().is_dir() {
+      let Ok(info) = parse_directory(filename, format) else {
+        continue;
+      };
+
+      let dir = entry.path();
+      let migration =
+        read_split_migration(info, dir.clone(), dir.join(UP_FILENAME), dir.join(DOWN_FILENAME))?;
+      insert_migration(&mut files, migration)?;
       continue;
-    };
+    }
 
-    let file = File::open(entry.path())?;
-    let mut buf_reader = BufReader::new(file);
-    let mut content = String::new();
-    buf_reader.read_to_string(&mut content)?;
+    if let Ok((info, direction)) = parse_paired_file(filename, format) {
+      let key = (info.number, info.filename.clone());
+      let halves = paired.entry(key).or_insert_with(|| (info, None, None));
+      match direction {
+        Direction::Up => halves.1 = Some(entry.path()),
+        Direction::Down => halves.2 = Some(entry.path()),
+      }
+      continue;
+    }
 
-    let split_vec: Vec<String> = content.split('\n').map(ToString::to_string).collect();
+    let Ok(info) = parse_file(filename, format) else {
+      continue;
+    };
 
-    let pos_up = split_vec
-      .iter()
-      .position(|s| s == "-- !UP" || s == "-- !UP\r")
-      .context("Parser can't find the UP migration")?;
-    let pos_down = split_vec
-      .iter()
-      .position(|s| s == "-- !DOWN" || s == "-- !DOWN\r")
-      .context("Parser can't find the DOWN migration")?;
+    let mut content = String::new();
+    File::open(entry.path())
+      .map(BufReader::new)
+      .and_then(|mut buf_reader| buf_reader.read_to_string(&mut content))
+      .map_err(|err| MidasError::io(format!("Failed to read {filename}"), err))?;
 
-    let content_up = &split_vec[(pos_up + 1)..pos_down];
-    let content_down = &split_vec[(pos_down + 1)..];
+    let (content_up, content_down) = parse_content(&info.filename, &content)?;
+    let no_transaction = content.lines().any(|s| s.trim_end() == NO_TRANSACTION_DIRECTIVE);
 
     let migration = MigrationFile {
-      content_up: Some(content_up.to_vec()),
-      content_down: Some(content_down.to_vec()),
+      content_up: Some(content_up),
+      content_down: Some(content_down),
+      no_transaction,
+      allowed_rules: allowed_rules(&content),
+      timeouts: parse_timeouts(&info.filename, &content)?,
+      checksum: checksum(&content),
+      path: entry.path(),
       ..info
     };
 
     log::trace!("Running the migration: {:?} {:?}", migration, migration.filename);
-    files.insert(migration.number, migration);
+    insert_migration(&mut files, migration)?;
+  }
+
+  for (info, up_path, down_path) in paired.into_values() {
+    let (Some(up_path), Some(down_path)) = (up_path, down_path) else {
+      return Err(MidasError::Parse {
+        reason: "The matching .up.sql or .down.sql file is missing".to_owned(),
+        filename: info.filename,
+      });
+    };
+
+    let migration = read_split_migration(info, up_path.clone(), up_path, down_path)?;
+    insert_migration(&mut files, migration)?;
   }
 
   Ok(files)
 }
 
-/// Generate a timestamp string
-fn timestamp() -> String {
-  let start = SystemTime::now();
-  let since_the_epoch = start.duration_since(UNIX_EPOCH).expect("Time went backwards");
-  since_the_epoch.as_millis().to_string()
+/// Build the list of repeatable migrations
+pub fn build_repeatable_list(path: &Path) -> MidasResult<RepeatableFiles> {
+  let mut files = RepeatableFiles::new();
+  let entries = fs::read_dir(path)
+    .map_err(|err| MidasError::io(format!("Failed to read the migrations directory {path:?}"), err))?
+    .filter_map(Result::ok);
+
+  for entry in entries {
+    let filename = entry.file_name().to_string_lossy().into_owned();
+    let Some(name) = parse_repeatable_file(&filename) else {
+      continue;
+    };
+    if !entry.path().is_file() {
+      continue;
+    }
+
+    let content = fs::read_to_string(entry.path())
+      .map_err(|err| MidasError::io(format!("Failed to read {filename}"), err))?;
+    let repeatable = RepeatableFile {
+      name: name.to_owned(),
+      no_transaction: content.lines().any(|s| s.trim_end() == NO_TRANSACTION_DIRECTIVE),
+      checksum: checksum(&content),
+      content: MigrationContent {
+        text: content,
+        line: 1,
+        path: None,
+      },
+      path: entry.path(),
+      filename,
+    };
+    files.insert(repeatable.name.clone(), repeatable);
+  }
+
+  Ok(files)
+}
+
+/// Build the list of callback scripts
+pub fn build_callback_list(path: &Path) -> MidasResult<CallbackFiles> {
+  let mut files = CallbackFiles::new();
+  for callback in Callback::ALL {
+    let filename = callback.filename();
+    let filepath = path.join(&filename);
+    if !filepath.is_file() {
+      continue;
+    }
+
+    let text = fs::read_to_string(&filepath)
+      .map_err(|err| MidasError::io(format!("Failed to read {filename}"), err))?;
+    files.insert(
+      callback,
+      CallbackFile {
+        callback,
+        filename,
+        path: filepath,
+        content: MigrationContent {
+          text,
+          line: 1,
+          path: None,
+        },
+      },
+    );
+  }
+
+  Ok(files)
+}
+
+/// A problem found in a migration file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+  /// A `.sql` file whose name doesn't match `<version>_<name>.sql`, its `.up.sql` and `.down.sql`
+  /// variants or `R_<name>.sql`
+  InvalidFilename,
+  /// Another migration file uses the same number
+  DuplicateNumber { other: String },
+  /// The `-- !UP` or `-- !DOWN` marker is missing
+  MissingMarker(&'static str),
+  /// The `-- !DOWN` marker comes before the `-- !UP` marker
+  MarkersOutOfOrder,
+  /// The UP section has no statements
+  EmptyUp,
+  /// The DOWN section has no statements
+  EmptyDown,
+  /// The repeatable migration has no statements
+  EmptyRepeatable,
+  /// The file is not valid UTF-8 starting at the given line
+  InvalidUtf8 { line: usize },
+  /// The other half of a paired or directory migration is missing
+  MissingFile(String),
+  /// A directive has an invalid value
+  InvalidDirective(String),
+}
+
+impl fmt::Display for Problem {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::InvalidFilename => {
+        write!(
+          f,
+          "filename does not match <version>_<name>.sql, .up.sql, .down.sql or R_<name>.sql"
+        )
+      },
+      Self::DuplicateNumber { other } => write!(f, "migration number is also used by {other}"),
+      Self::MissingMarker(marker) => write!(f, "missing the `-- !{marker}` marker"),
+      Self::MarkersOutOfOrder => write!(f, "the `-- !DOWN` marker comes before the `-- !UP` marker"),
+      Self::EmptyUp => write!(f, "the UP section is empty"),
+      Self::EmptyDown => write!(f, "the DOWN section is empty"),
+      Self::EmptyRepeatable => write!(f, "the repeatable migration is empty"),
+      Self::InvalidUtf8 { line } => write!(f, "content is not valid UTF-8 at line {line}"),
+      Self::MissingFile(filename) => write!(f, "missing the {filename} file"),
+      Self::InvalidDirective(reason) => write!(f, "{reason}"),
+    }
+  }
+}
+
+/// A problem found in the migrations directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationIssue {
+  /// The migration filename
+  pub filename: String,
+  /// What is wrong with the file
+  pub problem: Problem,
+}
+
+/// The result of checking every migration file in a directory
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+  /// The number of `.sql` files and migration directories checked
+  pub files: usize,
+  /// Every problem found, ordered by filename
+  pub issues: Vec<ValidationIssue>,
 }
 
-// Create a new migration file
-pub fn create_migration_file(path: &Path, slug: &str) -> AnyhowResult<()> {
-  let filename = timestamp() + "_" + slug + ".sql";
-  let filepath = path.join(filename);
+/// Whether a section has anything besides blank lines and line comments
+fn has_statements(text: &str) -> bool {
+  text.lines().any(|line| {
+    let line = line.trim();
+    !line.is_empty() && !line.starts_with("--")
+  })
+}
+
+/// Decode a migration file, pointing at the first line that is not valid UTF-8
+fn decode(content: &[u8]) -> Result<&str, Problem> {
+  std::str::from_utf8(content).map_err(|err| {
+    let line = content[..err.valid_up_to()]
+      .iter()
+      .filter(|b| **b == b'\n')
+      .count()
+      + 1;
+    Problem::InvalidUtf8 { line }
+  })
+}
+
+/// Check the contents of a migration file
+fn validate_content(content: &[u8]) -> Vec<Problem> {
+  let content = match decode(content) {
+    Ok(content) => content,
+    Err(problem) => return vec![problem],
+  };
+
+  let up = find_marker(content, "-- !UP");
+  let down = find_marker(content, "-- !DOWN");
+  let mut problems = Vec::new();
+  if let Err(reason) = timeouts(content) {
+    problems.push(Problem::InvalidDirective(reason));
+  }
+  match (up, down) {
+    (Some((_, up_end, _)), Some((down_start, down_end, _))) if up_end <= down_start => {
+      if !has_statements(&content[up_end..down_start]) {
+        problems.push(Problem::EmptyUp);
+      }
+      if !has_statements(&content[down_end..]) {
+        problems.push(Problem::EmptyDown);
+      }
+    },
+    (Some(_), Some(_)) => problems.push(Problem::MarkersOutOfOrder),
+    _ => {
+      if up.is_none() {
+        problems.push(Problem::MissingMarker("UP"));
+      }
+      if down.is_none() {
+        problems.push(Problem::MissingMarker("DOWN"));
+      }
+    },
+  }
+  problems
+}
+
+/// Check a file holding only the UP or DOWN section of a migration
+fn validate_section(path: &Path, direction: Direction) -> MidasResult<Vec<Problem>> {
+  let filename = path
+    .file_name()
+    .map(|filename| filename.to_string_lossy().into_owned())
+    .unwrap_or_default();
+  if !path.is_file() {
+    return Ok(vec![Problem::MissingFile(filename)]);
+  }
+
+  let content = fs::read(path).map_err(|err| MidasError::io(format!("Failed to read {filename}"), err))?;
+  let problem = match decode(&content) {
+    Err(problem) => problem,
+    Ok(content) => match timeouts(content) {
+      Err(reason) => Problem::InvalidDirective(reason),
+      Ok(_) if has_statements(content) => return Ok(Vec::new()),
+      Ok(_) if direction == Direction::Up => Problem::EmptyUp,
+      Ok(_) => Problem::EmptyDown,
+    },
+  };
+  Ok(vec![problem])
+}
+
+/// Check every migration file in the directory and report all the problems found
+pub fn validate_migrations(path: &Path, format: VersionFormat) -> MidasResult<ValidationReport> {
+  let mut entries = fs::read_dir(path)
+    .map_err(|err| MidasError::io(format!("Failed to read the migrations directory {path:?}"), err))?
+    .filter_map(Result::ok)
+    .filter(|entry| {
+      let path = entry.path();
+      if path.is_dir() {
+        return parse_directory(&entry.file_name().to_string_lossy(), format).is_ok();
+      }
+      path.extension().is_some_and(|ext| ext == "sql")
+    })
+    .collect::<Vec<_>>();
+  entries.sort_by_key(|entry| entry.file_name());
+
+  let mut report = ValidationReport::default();
+  // The migration each number belongs to, paired files share their stem
+  let mut numbers: BTreeMap<i64, String> = BTreeMap::new();
+  for entry in entries {
+    report.files += 1;
+    let filename = entry.file_name().to_string_lossy().into_owned();
+    let mut problems = Vec::new();
+
+    // Repeatable migrations and callback scripts have no number nor markers
+    let repeatable = parse_repeatable_file(&filename).is_some();
+    if (repeatable || Callback::from_filename(&filename).is_some()) && entry.path().is_file() {
+      let content =
+        fs::read(entry.path()).map_err(|err| MidasError::io(format!("Failed to read {filename}"), err))?;
+      match decode(&content) {
+        Err(problem) => problems.push(problem),
+        Ok(content) if repeatable && !has_statements(content) => problems.push(Problem::EmptyRepeatable),
+        Ok(_) => {},
+      }
+      report
+        .issues
+        .extend(problems.into_iter().map(|problem| ValidationIssue {
+          filename: filename.clone(),
+          problem,
+        }));
+      continue;
+    }
+
+    let parsed = if entry.path().is_dir() {
+      parse_directory(&filename, format).map(|info| (info, filename.clone()))
+    } else if let Ok((info, _)) = parse_paired_file(&filename, format) {
+      let stem = info.filename.trim_end_matches(".up.sql").to_owned();
+      Ok((info, stem))
+    } else {
+      parse_file(&filename, format).map(|info| (info, filename.clone()))
+    };
+    match parsed {
+      Ok((info, migration)) => match numbers.get(&info.number) {
+        Some(other) if *other != migration => {
+          problems.push(Problem::DuplicateNumber { other: other.clone() });
+        },
+        Some(_) => {},
+        None => {
+          numbers.insert(info.number, migration);
+        },
+      },
+      Err(_) => problems.push(Problem::InvalidFilename),
+    }
+
+    if entry.path().is_dir() {
+      problems.extend(validate_section(&entry.path().join(UP_FILENAME), Direction::Up)?);
+      problems.extend(validate_section(
+        &entry.path().join(DOWN_FILENAME),
+        Direction::Down,
+      )?);
+    } else if let Ok((info, direction)) = parse_paired_file(&filename, format) {
+      problems.extend(validate_section(&entry.path(), direction)?);
+
+      // Only the UP file reports a missing DOWN file and the other way around
+      let stem = info.filename.trim_end_matches(".up.sql");
+      let other = match direction {
+        Direction::Up => format!("{stem}.down.sql"),
+        Direction::Down => format!("{stem}.up.sql"),
+      };
+      if !path.join(&other).is_file() {
+        problems.push(Problem::MissingFile(other));
+      }
+    } else {
+      let content =
+        fs::read(entry.path()).map_err(|err| MidasError::io(format!("Failed to read {filename}"), err))?;
+      problems.extend(validate_content(&content));
+    }
+
+    report
+      .issues
+      .extend(problems.into_iter().map(|problem| ValidationIssue {
+        filename: filename.clone(),
+        problem,
+      }));
+  }
+
+  Ok(report)
+}
 
+/// Write a new file and flush it to disk
+fn write_file(filepath: &Path, contents: &str) -> MidasResult<()> {
   log::trace!("Creating new migration file: {:?}", filepath);
-  let mut f = File::create(filepath)?;
-  let contents = indoc! {"\
-    -- # Put your SQL below migration seperator.
-    -- !UP
+  let mut f =
+    File::create(filepath).map_err(|err| MidasError::io(format!("Failed to create {filepath:?}"), err))?;
+  f.write_all(contents.as_bytes())
+    .and_then(|()| f.sync_all())
+    .map_err(|err| MidasError::io(format!("Failed to write {filepath:?}"), err))
+}
+
+/// Split a rendered template on its markers into the contents of separate UP and DOWN files
+/// Directives and comments above the `-- !UP` marker are kept at the top of the UP file
+fn split_template(filename: &str, contents: &str) -> MidasResult<(String, String)> {
+  let (up, down) = parse_content(filename, contents)?;
+  let preamble = find_marker(contents, "-- !UP").map_or("", |(start, _, _)| &contents[..start]);
+  let up = format!("{preamble}{}", up.text.trim_start_matches(['\r', '\n']));
+  let down = down.text.trim_start_matches(['\r', '\n']).to_owned();
+  Ok((up, down))
+}
 
-    -- !DOWN
-  "};
+// Create a new migration from a template in the given layout, returning its path
+pub fn create_migration_file(
+  path: &Path,
+  version: &str,
+  slug: &str,
+  template: &str,
+  table: Option<&str>,
+  layout: Layout,
+) -> MidasResult<PathBuf> {
+  let stem = format!("{version}_{slug}");
 
-  f.write_all(contents.as_bytes())?;
-  f.sync_all()?;
+  let author = current_os_user();
+  let placeholders = Placeholders {
+    name: slug,
+    table: table.unwrap_or_else(|| table_from_slug(slug)),
+    timestamp: version,
+    author: &author,
+  };
+  let contents = render(template, &placeholders);
 
-  Ok(())
+  match layout {
+    Layout::Single => {
+      let filepath = path.join(format!("{stem}.sql"));
+      write_file(&filepath, &contents)?;
+      Ok(filepath)
+    },
+    Layout::Paired => {
+      let (up, down) = split_template(&stem, &contents)?;
+      let filepath = path.join(format!("{stem}.up.sql"));
+      write_file(&filepath, &up)?;
+      write_file(&path.join(format!("{stem}.down.sql")), &down)?;
+      Ok(filepath)
+    },
+    Layout::Directory => {
+      let (up, down) = split_template(&stem, &contents)?;
+      let dir = path.join(&stem);
+      fs::create_dir(&dir).map_err(|err| MidasError::io(format!("Failed to create {dir:?}"), err))?;
+      write_file(&dir.join(UP_FILENAME), &up)?;
+      write_file(&dir.join(DOWN_FILENAME), &down)?;
+      Ok(dir)
+    },
+  }
 }
 
 #[cfg(test)]
@@ -138,8 +977,309 @@ mod tests {
 
   #[test]
   fn it_should_parse_correct_migration_filename() {
-    let result = parse_file("0000000000000_initial.sql").unwrap();
+    let result = parse_file("0000000000000_initial.sql", VersionFormat::Timestamp).unwrap();
     assert_eq!(result.number, 0);
     assert_eq!(result.filename, "0000000000000_initial.sql");
+    assert_eq!(result.name(), "initial");
+  }
+
+  #[test]
+  fn it_should_detect_no_transaction_directive() {
+    let dir = assert_fs::TempDir::new().unwrap();
+    fs::write(
+      dir.path().join("0000000000001_plain.sql"),
+      "-- !UP\ncreate table a (id int);\n-- !DOWN\ndrop table a;\n",
+    )
+    .unwrap();
+    fs::write(
+      dir.path().join("0000000000002_concurrent.sql"),
+      "-- !NO_TRANSACTION\n-- !UP\ncreate index concurrently a_idx on a (id);\n-- !DOWN\ndrop index a_idx;\n",
+    )
+    .unwrap();
+
+    let files = build_migration_list(dir.path(), VersionFormat::Timestamp).unwrap();
+
+    assert!(!files[&1].no_transaction);
+    assert!(files[&2].no_transaction);
+  }
+
+  #[test]
+  fn it_should_read_timeout_directives() {
+    let content = "-- !TIMEOUT 30s\n-- !LOCK_TIMEOUT 500ms\n-- !UP\nalter table a add b int;\n-- !DOWN\n";
+    let timeouts = parse_timeouts("0000000000001_a.sql", content).unwrap();
+    assert_eq!(timeouts.statement, Some(std::time::Duration::from_secs(30)));
+    assert_eq!(timeouts.lock, Some(std::time::Duration::from_millis(500)));
+
+    assert!(matches!(
+      parse_timeouts("0000000000001_a.sql", "-- !TIMEOUT soon\n"),
+      Err(MidasError::Parse { .. })
+    ));
+    assert!(
+      validate_content(b"-- !LOCK_TIMEOUT 5\n-- !UP\nselect 1;\n-- !DOWN\nselect 2;\n")
+        .iter()
+        .any(|problem| matches!(problem, Problem::InvalidDirective(_)))
+    );
+  }
+
+  #[test]
+  fn it_should_keep_sections_verbatim() {
+    let content = "-- header\r\n-- !UP\r\ncomment on table a is 'first\r\n\r\nsecond';\r\n\r\n-- !DOWN\r\ndrop table a;\r\n";
+    let (up, down) = parse_content("0000000000001_a.sql", content).unwrap();
+    assert_eq!(up.text, "comment on table a is 'first\r\n\r\nsecond';\r\n\r\n");
+    assert_eq!(up.line, 3);
+    assert_eq!(down.text, "drop table a;\r\n");
+    assert_eq!(down.line, 8);
+
+    assert!(matches!(
+      parse_content("0000000000001_a.sql", "-- !DOWN\n-- !UP\n"),
+      Err(MidasError::Parse { .. })
+    ));
+    assert!(matches!(
+      parse_content("0000000000001_a.sql", "-- !UP\n"),
+      Err(MidasError::MissingMarker { marker: "DOWN", .. })
+    ));
+  }
+
+  #[test]
+  fn it_should_compute_sha256_checksum() {
+    assert_eq!(
+      checksum("-- !UP\n-- !DOWN\n"),
+      "29e7001b9b7e9fe2fe2b0fdf8ee96be69a2e2096b62063a681dd70a70a6494d2"
+    );
+  }
+
+  #[test]
+  fn it_should_report_every_problem_in_the_directory() {
+    let dir = assert_fs::TempDir::new().unwrap();
+    let files: &[(&str, &[u8])] = &[
+      (
+        "0000000000001_valid.sql",
+        b"-- !UP\ncreate table a (id int);\n-- !DOWN\ndrop table a;\n",
+      ),
+      (
+        "0000000000001_twin.sql",
+        b"-- !UP\nselect 1;\n-- !DOWN\nselect 2;\n",
+      ),
+      ("1_misnamed.sql", b"-- !UP\nselect 1;\n-- !DOWN\nselect 2;\n"),
+      ("0000000000002_unmarked.sql", b"create table b (id int);\n"),
+      (
+        "0000000000003_reversed.sql",
+        b"-- !DOWN\nselect 2;\n-- !UP\nselect 1;\n",
+      ),
+      ("0000000000004_empty.sql", b"-- !UP\n-- nothing yet\n\n-- !DOWN\n"),
+      ("0000000000005_binary.sql", b"-- !UP\nselect '\xff';\n-- !DOWN\n"),
+      ("README.md", b"not a migration"),
+    ];
+    for (filename, content) in files {
+      fs::write(dir.path().join(filename), content).unwrap();
+    }
+
+    let report = validate_migrations(dir.path(), VersionFormat::Timestamp).unwrap();
+    let issues = report
+      .issues
+      .iter()
+      .map(|issue| (issue.filename.as_str(), issue.problem.clone()))
+      .collect::<Vec<_>>();
+
+    assert_eq!(report.files, 7);
+    assert_eq!(
+      issues,
+      vec![
+        (
+          "0000000000001_valid.sql",
+          Problem::DuplicateNumber {
+            other: "0000000000001_twin.sql".to_owned()
+          }
+        ),
+        ("0000000000002_unmarked.sql", Problem::MissingMarker("UP")),
+        ("0000000000002_unmarked.sql", Problem::MissingMarker("DOWN")),
+        ("0000000000003_reversed.sql", Problem::MarkersOutOfOrder),
+        ("0000000000004_empty.sql", Problem::EmptyUp),
+        ("0000000000004_empty.sql", Problem::EmptyDown),
+        ("0000000000005_binary.sql", Problem::InvalidUtf8 { line: 2 }),
+        ("1_misnamed.sql", Problem::InvalidFilename),
+      ]
+    );
+  }
+
+  #[test]
+  fn it_should_merge_every_layout() {
+    let dir = assert_fs::TempDir::new().unwrap();
+    fs::write(
+      dir.path().join("0000000000001_single.sql"),
+      "-- !UP\ncreate table a (id int);\n-- !DOWN\ndrop table a;\n",
+    )
+    .unwrap();
+    fs::write(
+      dir.path().join("0000000000002_paired.up.sql"),
+      "create table b (id int);\n",
+    )
+    .unwrap();
+    fs::write(
+      dir.path().join("0000000000002_paired.down.sql"),
+      "-- !NO_TRANSACTION\ndrop table b;\n",
+    )
+    .unwrap();
+    fs::create_dir_all(dir.path().join("0000000000003_folder")).unwrap();
+    fs::write(
+      dir.path().join("0000000000003_folder/up.sql"),
+      "create table c (id int);\n",
+    )
+    .unwrap();
+    fs::write(
+      dir.path().join("0000000000003_folder/down.sql"),
+      "drop table c;\n",
+    )
+    .unwrap();
+    fs::create_dir_all(dir.path().join(".templates")).unwrap();
+
+    let files = build_migration_list(dir.path(), VersionFormat::Timestamp).unwrap();
+    assert_eq!(files.keys().copied().collect::<Vec<_>>(), vec![1, 2, 3]);
+
+    let paired = &files[&2];
+    assert_eq!(paired.filename, "0000000000002_paired.up.sql");
+    assert_eq!(paired.name(), "paired");
+    assert!(paired.no_transaction);
+    let down = paired.content_down.as_ref().unwrap();
+    assert_eq!(down.text, "-- !NO_TRANSACTION\ndrop table b;\n");
+    assert_eq!(
+      down.path.as_deref(),
+      Some(dir.path().join("0000000000002_paired.down.sql").as_path())
+    );
+
+    let folder = &files[&3];
+    assert_eq!(folder.name(), "folder");
+    assert_eq!(folder.path, dir.path().join("0000000000003_folder"));
+    assert_eq!(folder.content_up.as_ref().unwrap().line, 1);
+    assert!(validate_migrations(dir.path(), VersionFormat::Timestamp)
+      .unwrap()
+      .issues
+      .is_empty());
+
+    fs::remove_file(dir.path().join("0000000000002_paired.down.sql")).unwrap();
+    assert!(matches!(
+      build_migration_list(dir.path(), VersionFormat::Timestamp),
+      Err(MidasError::Parse { .. })
+    ));
+    let issues = validate_migrations(dir.path(), VersionFormat::Timestamp)
+      .unwrap()
+      .issues;
+    assert_eq!(
+      issues,
+      vec![ValidationIssue {
+        filename: "0000000000002_paired.up.sql".to_owned(),
+        problem: Problem::MissingFile("0000000000002_paired.down.sql".to_owned()),
+      }]
+    );
+  }
+
+  #[test]
+  fn it_should_refuse_halves_of_different_migrations_sharing_a_number() {
+    let dir = assert_fs::TempDir::new().unwrap();
+    fs::write(dir.path().join("0001_a.up.sql"), "create table a (id int);\n").unwrap();
+    fs::write(dir.path().join("0001_b.down.sql"), "drop table b;\n").unwrap();
+
+    let result = build_migration_list(dir.path(), VersionFormat::Sequential);
+    assert!(matches!(
+      result,
+      Err(MidasError::Parse { filename, .. }) if filename == "0001_a.up.sql"
+    ));
+
+    // Complete pairs sharing a number are refused too
+    fs::write(dir.path().join("0001_a.down.sql"), "drop table a;\n").unwrap();
+    fs::write(dir.path().join("0001_b.up.sql"), "create table b (id int);\n").unwrap();
+    let result = build_migration_list(dir.path(), VersionFormat::Sequential);
+    assert!(matches!(
+      result,
+      Err(MidasError::Parse { filename, reason })
+        if filename == "0001_b.up.sql" && reason.contains("0001_a.up.sql")
+    ));
+  }
+
+  #[test]
+  fn it_should_refuse_layouts_sharing_a_number() {
+    let dir = assert_fs::TempDir::new().unwrap();
+    fs::write(
+      dir.path().join("0002_c.sql"),
+      "-- !UP\ncreate table c (id int);\n-- !DOWN\ndrop table c;\n",
+    )
+    .unwrap();
+    fs::write(dir.path().join("0002_d.up.sql"), "create table d (id int);\n").unwrap();
+    fs::write(dir.path().join("0002_d.down.sql"), "drop table d;\n").unwrap();
+
+    let result = build_migration_list(dir.path(), VersionFormat::Sequential);
+    assert!(matches!(
+      result,
+      Err(MidasError::Parse { filename, reason })
+        if filename == "0002_d.up.sql" && reason.contains("0002_c.sql")
+    ));
+  }
+
+  #[test]
+  fn it_should_parse_and_generate_every_version_format() {
+    let info = parse_file("20240131235959_create_users.sql", VersionFormat::Datetime).unwrap();
+    assert_eq!(info.number, 20240131235959);
+    assert!(parse_file("20240131235959_create_users.sql", VersionFormat::Timestamp).is_err());
+
+    let info = parse_file("0002_create_posts.sql", VersionFormat::Sequential).unwrap();
+    assert_eq!(info.number, 2);
+    assert_eq!(info.name(), "create_posts");
+    assert_eq!(VersionFormat::Sequential.format(info.number), "0002");
+
+    let mut migrations = MigrationFiles::new();
+    assert_eq!(VersionFormat::Sequential.next(&migrations), "0001");
+    migrations.insert(9, info);
+    assert_eq!(VersionFormat::Sequential.next(&migrations), "0010");
+    assert_eq!(VersionFormat::Datetime.next(&migrations).len(), 14);
+    assert_eq!(VersionFormat::Timestamp.next(&migrations).len(), 13);
+  }
+
+  #[test]
+  fn it_should_find_repeatable_migrations_and_callbacks() {
+    let dir = assert_fs::TempDir::new().unwrap();
+    fs::write(
+      dir.path().join("0000000000001_create_users.sql"),
+      "-- !UP\ncreate table users (id int);\n-- !DOWN\ndrop table users;\n",
+    )
+    .unwrap();
+    fs::write(
+      dir.path().join("R_users_view.sql"),
+      "create view users_view as select id from users;\n",
+    )
+    .unwrap();
+    fs::write(dir.path().join("R_empty.sql"), "-- nothing yet\n").unwrap();
+    fs::write(dir.path().join("_after_all.sql"), "analyze;\n").unwrap();
+
+    let repeatables = build_repeatable_list(dir.path()).unwrap();
+    assert_eq!(
+      repeatables.keys().collect::<Vec<_>>(),
+      vec!["empty", "users_view"]
+    );
+    let view = &repeatables["users_view"];
+    assert_eq!(view.filename, "R_users_view.sql");
+    assert_eq!(view.content.line, 1);
+    assert_eq!(view.checksum, checksum(&view.content.text));
+
+    let files = build_migration_list(dir.path(), VersionFormat::Sequential).unwrap();
+    assert_eq!(files.keys().copied().collect::<Vec<_>>(), vec![1]);
+    let callbacks = build_callback_list(dir.path()).unwrap();
+    assert_eq!(
+      callbacks.keys().copied().collect::<Vec<_>>(),
+      vec![Callback::AfterAll]
+    );
+    assert_eq!(
+      Callback::from_filename("_after_all.sql"),
+      Some(Callback::AfterAll)
+    );
+    let issues = validate_migrations(dir.path(), VersionFormat::Timestamp)
+      .unwrap()
+      .issues;
+    assert_eq!(
+      issues,
+      vec![ValidationIssue {
+        filename: "R_empty.sql".to_owned(),
+        problem: Problem::EmptyRepeatable,
+      }]
+    );
   }
 }
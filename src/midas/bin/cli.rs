@@ -6,37 +6,83 @@ use anyhow::{
 };
 use clap::{
   Arg,
+  ArgAction,
+  ArgMatches,
   Command,
 };
 use clap_complete::Shell;
 use console::style;
 use indoc::formatdoc;
-use midas_core::commander::Migrator;
-use midas_core::lookup::MigrationFiles;
+use midas_core::commander::{
+  Direction,
+  Migrator,
+  PlannedMigration,
+};
+use midas_core::linter::{
+  self,
+  Severity,
+};
+use midas_core::lookup::{
+  Layout,
+  MigrationFiles,
+  VersionFormat,
+};
+use midas_core::report::{
+  HistoryEntry,
+  MigrationState,
+  RepeatableState,
+  StatusReport,
+};
 use midas_core::sequel::mysql::Mysql;
-use midas_core::sequel::postgres::Postgres;
+use midas_core::sequel::postgres::{
+  Postgres,
+  SslMode,
+  SslOptions,
+};
 use midas_core::sequel::sqlite::Sqlite;
-use midas_core::sequel::Driver as SequelDriver;
+use midas_core::sequel::{
+  retry_connect,
+  Driver as SequelDriver,
+  Timeouts,
+  TrackingTable,
+  DEFAULT_MIGRATIONS_TABLE,
+};
+use midas_core::splitter::Dialect;
+use midas_core::variables::Variables;
 use midas_core::{
   ensure_migration_state_dir_exists,
   lookup,
+  template,
 };
 use prettytable::format::consts;
 use prettytable::{
+  color,
   row,
+  Attr,
+  Cell,
+  Row,
   Table,
 };
+use serde::Serialize;
 use std::fs::File;
 use std::io::Write as _;
-use std::path::Path;
+use std::path::{
+  Path,
+  PathBuf,
+};
 use std::str::FromStr;
-use std::time::Instant;
+use std::time::{
+  Duration,
+  Instant,
+};
 use std::{
   env,
   fs,
 };
 use tracing_subscriber::EnvFilter;
 
+use crate::reporter::OutputMode;
+
 /// The package version
 const PKG_VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -57,8 +103,10 @@ pub fn midas_entry(command_name: &str, is_subcommand: bool) -> AnyhowResult<()>
   }
 
   // Initialize the logger
+  // Logs go to stderr so they never mix with the report written to stdout
   tracing_subscriber::fmt()
     .with_env_filter(EnvFilter::from_default_env())
+    .with_writer(std::io::stderr)
     .init();
 
   let mut cli_app = build_cli(command_name, is_subcommand);
@@ -120,8 +168,100 @@ pub fn midas_entry(command_name: &str, is_subcommand: bool) -> AnyhowResult<()>
     fs::create_dir_all(source_path)?;
   }
 
+  // Set how migration versions are parsed, generated and displayed
+  let version_format = matches
+    .get_one::<String>("version_format")
+    .context("Version format was invalid")?
+    .parse::<VersionFormat>()
+    .map_err(anyhow::Error::msg)?;
+
+  // Set where the applied migrations are recorded
+  let migrations_table = matches
+    .get_one::<String>("migrations_table")
+    .context("Migrations table was invalid")?;
+  let migrations_schema = matches.get_one::<String>("migrations_schema");
+  let mut tracking = TrackingTable::new(migrations_table, migrations_schema.map(String::as_str))?;
+  if matches.get_flag("create_schema") {
+    let grant_to = matches.get_one::<String>("grant_schema_to");
+    tracking = tracking.with_schema_creation(grant_to.map(String::as_str))?;
+  }
+
+  // Set how connecting to a database that is still starting up is retried
+  let connect = ConnectOptions {
+    timeout: matches.get_one::<Duration>("connect_timeout").copied(),
+    retries: matches
+      .get_one::<u32>("connect_retries")
+      .copied()
+      .context("Connect retries count was invalid")?,
+    tracking,
+    sslmode: matches
+      .get_one::<String>("sslmode")
+      .map(|mode| mode.parse::<SslMode>())
+      .transpose()
+      .map_err(anyhow::Error::msg)?,
+    sslrootcert: matches.get_one::<PathBuf>("sslrootcert").cloned(),
+    sslcert: matches.get_one::<PathBuf>("sslcert").cloned(),
+    sslkey: matches.get_one::<PathBuf>("sslkey").cloned(),
+  };
+
+  // Block until the database accepts connections, without touching the migrations
+  if let Some(wait_matches) = matches.subcommand_matches("wait") {
+    let timeout = wait_matches
+      .get_one::<Duration>("timeout")
+      .copied()
+      .context("Wait timeout was invalid")?;
+    return wait(db_url, &connect, timeout);
+  }
+
+  // Validate the whole migrations directory, building the migrations list stops at the first problem
+  if matches.subcommand_name() == Some("validate") {
+    return validate(source_path, version_format);
+  }
+
   // Create the migrations tree list
-  let migrations = lookup::build_migration_list(source_path)?;
+  let migrations = lookup::build_migration_list(source_path, version_format)?;
+  let repeatables = lookup::build_repeatable_list(source_path)?;
+  let callbacks = lookup::build_callback_list(source_path)?;
+
+  // Set how long to wait for another migrator holding the migration lock
+  let lock_timeout = matches
+    .get_one::<u64>("lock_wait")
+    .map(|secs| Duration::from_secs(*secs))
+    .context("Lock wait timeout was invalid")?;
+
+  // Set the statement and lock timeouts of migrations without timeout directives
+  let timeouts = Timeouts {
+    statement: matches.get_one::<Duration>("statement_timeout").copied(),
+    lock: matches.get_one::<Duration>("lock_timeout").copied(),
+  };
+
+  // Retry migrations that hit the lock timeout
+  let lock_retries = matches
+    .get_one::<u32>("lock_retries")
+    .copied()
+    .context("Lock retries count was invalid")?;
+  let lock_retry_delay = matches
+    .get_one::<Duration>("lock_retry_delay")
+    .copied()
+    .context("Lock retry delay was invalid")?;
+
+  // Print the migrations plan instead of executing it
+  let dry_run = matches.get_flag("dry_run");
+
+  // Run even when applied migrations are missing on disk
+  let ignore_missing = matches.get_flag("ignore_missing");
+
+  // Set the values of the ${NAME} placeholders, falling back to the environment and dotenv files
+  let mut variables = Variables::from_env();
+  for assignment in matches.get_many::<String>("var").into_iter().flatten() {
+    let (name, value) = assignment
+      .split_once('=')
+      .with_context(|| format!("Variable {assignment} must be written as key=value"))?;
+    variables.set(name, value);
+  }
+
+  // Set how the progress of migration runs is rendered
+  let output = OutputMode::from_arg(matches.get_one::<String>("output").map(String::as_str));
 
   // Start the timer for monitoring the operation duration
   let start = Instant::now();
@@ -129,30 +269,86 @@ pub fn midas_entry(command_name: &str, is_subcommand: bool) -> AnyhowResult<()>
   // Match the subcommand and execute the appropriate action
   match matches.subcommand_name() {
     Some("create") => {
-      let slug = matches
+      let create_matches = matches
         .subcommand_matches("create")
-        .context("No subcommand name argument was detected")?
+        .context("No subcommand name argument was detected")?;
+      let slug = create_matches
         .get_one::<String>("name")
         .context("Name argument was either malformed or undecipherable")?;
+      let template = create_matches.get_one::<String>("template").map(String::as_str);
+      let table = create_matches.get_one::<String>("table").map(String::as_str);
+      let layout = create_matches
+        .get_one::<String>("layout")
+        .context("Migration layout was invalid")?
+        .parse::<Layout>()
+        .map_err(anyhow::Error::msg)?;
 
-      create(source_path, slug)?;
+      let version = version_format.next(&migrations);
+      create(
+        source_path,
+        &version,
+        slug,
+        template,
+        table,
+        layout,
+        dialect_from_url(db_url),
+      )?;
+    },
+    Some("lint") => {
+      let fail_on = matches
+        .subcommand_matches("lint")
+        .and_then(|matches| matches.get_one::<String>("fail_on"))
+        .context("Severity threshold was invalid")?
+        .parse::<Severity>()
+        .map_err(anyhow::Error::msg)?;
+      lint(&migrations, db_url, fail_on)?;
     },
     Some("list") => {
-      list_migration_files(&migrations)?;
+      let format = subcommand_format(&matches, "list")?;
+      list_migration_files(&migrations, format, version_format)?;
     },
     Some("faker") => {
       unimplemented!();
     },
     Some("init") => init(source, db_url)?,
     Some("status") => {
-      let executor = get_executor(db_url);
+      let executor = get_executor(db_url, &connect, false);
+      let mut migrator =
+        executor.map(|executor| Migrator::new(executor, migrations).with_repeatables(repeatables))?;
+      let report = migrator.status()?;
+      match subcommand_format(&matches, "status")? {
+        "table" => print_status(&report, version_format),
+        format => print_document(&report, format)?,
+      }
+    },
+    Some("history") => {
+      let executor = get_executor(db_url, &connect, false);
       let mut migrator = executor.map(|executor| Migrator::new(executor, migrations))?;
-      migrator.status()?
+      print_history(&migrator.history()?, version_format);
     },
     Some("up") => {
-      let executor = get_executor(db_url);
-      let mut migrator = executor.map(|executor| Migrator::new(executor, migrations))?;
-      migrator.up()?
+      let has_migrations = !migrations.is_empty() || !repeatables.is_empty();
+      let executor = get_executor(db_url, &connect, dry_run);
+      let mut migrator = executor.map(|executor| {
+        Migrator::new(executor, migrations)
+          .with_read_only(dry_run)
+          .with_repeatables(repeatables)
+          .with_lock_timeout(lock_timeout)
+          .with_ignore_missing(ignore_missing)
+          .with_version_format(version_format)
+          .with_variables(variables.clone())
+          .with_callbacks(callbacks)
+          .with_timeouts(timeouts)
+          .with_lock_retries(lock_retries, lock_retry_delay)
+          .with_observer(output.reporter(version_format))
+      })?;
+      if dry_run {
+        print_plan(&migrator.plan_up()?, version_format);
+      } else if !has_migrations {
+        output.message("There are no available migration files.");
+      } else if migrator.up()?.is_empty() {
+        output.message("Migrations are all up-to-date.");
+      }
     },
     Some("upto") => {
       let value = matches
@@ -173,23 +369,79 @@ pub fn midas_entry(command_name: &str, is_subcommand: bool) -> AnyhowResult<()>
         ));
       }
 
-      let executor = get_executor(db_url);
-      let mut migrator = executor.map(|executor| Migrator::new(executor, migrations))?;
-      migrator.upto(migration_number)?
+      let executor = get_executor(db_url, &connect, dry_run);
+      let mut migrator = executor.map(|executor| {
+        Migrator::new(executor, migrations)
+          .with_read_only(dry_run)
+          .with_lock_timeout(lock_timeout)
+          .with_ignore_missing(ignore_missing)
+          .with_version_format(version_format)
+          .with_variables(variables.clone())
+          .with_callbacks(callbacks)
+          .with_timeouts(timeouts)
+          .with_lock_retries(lock_retries, lock_retry_delay)
+          .with_observer(output.reporter(version_format))
+      })?;
+      if dry_run {
+        print_plan(&migrator.plan_upto(migration_number)?, version_format);
+      } else if migrator.upto(migration_number)?.is_empty() {
+        output.message("Migrations are all up-to-date.");
+      }
     },
     Some("down") => {
-      let executor = get_executor(db_url);
-      let mut migrator = executor.map(|executor| Migrator::new(executor, migrations))?;
-      migrator.down()?
+      let executor = get_executor(db_url, &connect, dry_run);
+      let mut migrator = executor.map(|executor| {
+        Migrator::new(executor, migrations)
+          .with_read_only(dry_run)
+          .with_lock_timeout(lock_timeout)
+          .with_ignore_missing(ignore_missing)
+          .with_version_format(version_format)
+          .with_variables(variables.clone())
+          .with_callbacks(callbacks)
+          .with_timeouts(timeouts)
+          .with_lock_retries(lock_retries, lock_retry_delay)
+          .with_observer(output.reporter(version_format))
+      })?;
+      if dry_run {
+        print_plan(&migrator.plan_down()?, version_format);
+      } else if migrator.down()?.is_empty() {
+        output.message("Migrations table is empty. No need to run down migrations.");
+      }
     },
     Some("redo") => {
-      let executor = get_executor(db_url);
-      let mut migrator = executor.map(|executor| Migrator::new(executor, migrations))?;
-      migrator.redo()?
+      let executor = get_executor(db_url, &connect, dry_run);
+      let mut migrator = executor.map(|executor| {
+        Migrator::new(executor, migrations)
+          .with_read_only(dry_run)
+          .with_lock_timeout(lock_timeout)
+          .with_ignore_missing(ignore_missing)
+          .with_version_format(version_format)
+          .with_variables(variables.clone())
+          .with_callbacks(callbacks)
+          .with_timeouts(timeouts)
+          .with_lock_retries(lock_retries, lock_retry_delay)
+          .with_observer(output.reporter(version_format))
+      })?;
+      if dry_run {
+        print_plan(&migrator.plan_redo()?, version_format);
+      } else {
+        migrator.redo()?;
+      }
     },
     Some("revert") => {
-      let executor = get_executor(db_url);
-      let mut migrator = executor.map(|executor| Migrator::new(executor, migrations))?;
+      let executor = get_executor(db_url, &connect, dry_run);
+      let mut migrator = executor.map(|executor| {
+        Migrator::new(executor, migrations)
+          .with_read_only(dry_run)
+          .with_lock_timeout(lock_timeout)
+          .with_ignore_missing(ignore_missing)
+          .with_version_format(version_format)
+          .with_variables(variables.clone())
+          .with_callbacks(callbacks)
+          .with_timeouts(timeouts)
+          .with_lock_retries(lock_retries, lock_retry_delay)
+          .with_observer(output.reporter(version_format))
+      })?;
 
       let value = matches
         .subcommand_matches("revert")
@@ -197,12 +449,14 @@ pub fn midas_entry(command_name: &str, is_subcommand: bool) -> AnyhowResult<()>
         .get_one::<usize>("steps")
         .context("Steps number was invalid")?;
 
-      for _ in 0usize..*value {
-        migrator.revert()?;
+      if dry_run {
+        print_plan(&migrator.plan_revert(*value)?, version_format);
+      } else if migrator.revert(*value)?.is_empty() {
+        output.message("Migrations table is empty. No need to run revert migrations.");
       }
     },
     Some("drop") => {
-      let executor = get_executor(db_url);
+      let executor = get_executor(db_url, &connect, false);
       let mut migrator = executor.map(|executor| Migrator::new(executor, migrations))?;
       migrator.drop(db_url)?
     },
@@ -284,22 +538,264 @@ fn build_cli(command_name: &str, is_subcommand: bool) -> Command {
         .num_args(1)
         .required(false),
     )
+    .arg(
+      Arg::new("lock_wait")
+        .long("lock-wait")
+        .env("MIGRATIONS_LOCK_WAIT")
+        .value_name("seconds")
+        .help("Sets how long to wait for another migrator to release the migration lock")
+        .num_args(1)
+        .value_parser(clap::value_parser!(u64))
+        .default_value("60"),
+    )
+    .arg(
+      Arg::new("migrations_table")
+        .long("migrations-table")
+        .env("MIGRATIONS_TABLE")
+        .value_name("name")
+        .help("Sets the table recording the applied migrations")
+        .num_args(1)
+        .default_value(DEFAULT_MIGRATIONS_TABLE),
+    )
+    .arg(
+      Arg::new("migrations_schema")
+        .long("migrations-schema")
+        .env("MIGRATIONS_SCHEMA")
+        .value_name("name")
+        .help(
+          "Sets the schema of the migrations table, midas on PostgreSQL and the current database otherwise",
+        )
+        .num_args(1),
+    )
+    .arg(
+      Arg::new("create_schema")
+        .long("create-schema")
+        .env("MIGRATIONS_CREATE_SCHEMA")
+        .help(
+          "Creates the schema set by --migrations-schema when it is missing, the default midas schema of \
+           PostgreSQL is always created",
+        )
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+      Arg::new("grant_schema_to")
+        .long("grant-schema-to")
+        .env("MIGRATIONS_GRANT_SCHEMA_TO")
+        .value_name("role")
+        .help("Grants all privileges on the created schema to this role")
+        .num_args(1)
+        .requires("create_schema"),
+    )
+    .arg(
+      Arg::new("sslmode")
+        .long("sslmode")
+        .value_name("mode")
+        .help("Sets how TLS is used with PostgreSQL, over the sslmode parameter of the database url")
+        .num_args(1)
+        .value_parser(["disable", "prefer", "require", "verify-ca", "verify-full"]),
+    )
+    .arg(
+      Arg::new("sslrootcert")
+        .long("sslrootcert")
+        .value_name("path")
+        .help("Sets the CA certificates trusted to sign the PostgreSQL server certificate")
+        .num_args(1)
+        .value_parser(clap::value_parser!(PathBuf)),
+    )
+    .arg(
+      Arg::new("sslcert")
+        .long("sslcert")
+        .value_name("path")
+        .help("Sets the client certificate presented to PostgreSQL")
+        .num_args(1)
+        .value_parser(clap::value_parser!(PathBuf)),
+    )
+    .arg(
+      Arg::new("sslkey")
+        .long("sslkey")
+        .value_name("path")
+        .help("Sets the private key of the client certificate")
+        .num_args(1)
+        .value_parser(clap::value_parser!(PathBuf)),
+    )
+    .arg(
+      Arg::new("connect_timeout")
+        .long("connect-timeout")
+        .env("MIGRATIONS_CONNECT_TIMEOUT")
+        .value_name("duration")
+        .help("Gives up on a connection attempt to the database after this, e.g. 5s")
+        .num_args(1)
+        .value_parser(humantime::parse_duration),
+    )
+    .arg(
+      Arg::new("connect_retries")
+        .long("connect-retries")
+        .env("MIGRATIONS_CONNECT_RETRIES")
+        .value_name("count")
+        .help("Sets how many times a failed connection is retried, with exponential backoff")
+        .num_args(1)
+        .value_parser(clap::value_parser!(u32))
+        .default_value("0"),
+    )
+    .arg(
+      Arg::new("statement_timeout")
+        .long("statement-timeout")
+        .env("MIGRATIONS_STATEMENT_TIMEOUT")
+        .value_name("duration")
+        .help("Cancels migration statements running longer than this, e.g. 30s")
+        .num_args(1)
+        .value_parser(humantime::parse_duration),
+    )
+    .arg(
+      Arg::new("lock_timeout")
+        .long("lock-timeout")
+        .env("MIGRATIONS_LOCK_TIMEOUT")
+        .value_name("duration")
+        .help("Fails migration statements waiting longer than this for a table or row lock, e.g. 5s")
+        .num_args(1)
+        .value_parser(humantime::parse_duration),
+    )
+    .arg(
+      Arg::new("lock_retries")
+        .long("lock-retries")
+        .env("MIGRATIONS_LOCK_RETRIES")
+        .value_name("count")
+        .help("Sets how many times a migration that hit the lock timeout is retried")
+        .num_args(1)
+        .value_parser(clap::value_parser!(u32))
+        .default_value("0"),
+    )
+    .arg(
+      Arg::new("lock_retry_delay")
+        .long("lock-retry-delay")
+        .env("MIGRATIONS_LOCK_RETRY_DELAY")
+        .value_name("duration")
+        .help("Sets the delay before the first lock timeout retry, doubled on every retry")
+        .num_args(1)
+        .value_parser(humantime::parse_duration)
+        .default_value("1s"),
+    )
+    .arg(
+      Arg::new("version_format")
+        .long("version-format")
+        .env("MIGRATIONS_VERSION_FORMAT")
+        .help(
+          "Sets how migration versions are written: epoch milliseconds, YYYYMMDDHHMMSS or 0001, 0002, ...",
+        )
+        .num_args(1)
+        .default_value("timestamp")
+        .value_parser(["timestamp", "datetime", "sequential"])
+        .global(true),
+    )
+    .arg(
+      Arg::new("var")
+        .long("var")
+        .value_name("key=value")
+        .help("Sets a variable substituted for ${key} in migrations, overriding the environment")
+        .num_args(1)
+        .action(ArgAction::Append)
+        .global(true),
+    )
+    .arg(
+      Arg::new("dry_run")
+        .long("dry-run")
+        .visible_alias("plan")
+        .help("Prints the migrations and SQL that would run without executing them")
+        .action(ArgAction::SetTrue)
+        .global(true),
+    )
+    .arg(
+      Arg::new("ignore_missing")
+        .long("ignore-missing")
+        .help("Runs even when applied migrations are missing on disk, skipping them when undoing")
+        .action(ArgAction::SetTrue)
+        .global(true),
+    )
+    .arg(
+      Arg::new("output")
+        .long("output")
+        .env("MIGRATIONS_OUTPUT")
+        .value_name("mode")
+        .help("Sets how migration progress is shown, detected from the terminal when omitted")
+        .num_args(1)
+        .value_parser(["tty", "plain", "json"])
+        .global(true),
+    )
     .subcommand(Command::new("init").about("Setup and creates initial migration directory and a dotenv file"))
     .subcommand(
       Command::new("create")
         .visible_alias("c")
         .about("Creates a timestamped migration file")
-        .arg(Arg::new("name").help("The migration action name").required(true)),
+        .arg(Arg::new("name").help("The migration action name").required(true))
+        .arg(
+          Arg::new("template")
+            .long("template")
+            .short('t')
+            .help("The template to start from, looked up in <source>/.templates before the built-in ones")
+            .num_args(1),
+        )
+        .arg(
+          Arg::new("table")
+            .long("table")
+            .help("The table substituted for {{table}}, guessed from the name by default")
+            .num_args(1),
+        )
+        .arg(
+          Arg::new("layout")
+            .long("layout")
+            .help("One .sql file with markers, paired .up.sql/.down.sql files or a directory per migration")
+            .env("MIGRATIONS_LAYOUT")
+            .num_args(1)
+            .default_value("single")
+            .value_parser(["single", "paired", "directory"]),
+        ),
     )
     .subcommand(
       Command::new("status")
         .visible_alias("s")
-        .about("Checks the status of the migration"),
+        .about("Checks the status of the migration")
+        .arg(format_arg()),
+    )
+    .subcommand(
+      Command::new("history")
+        .visible_alias("h")
+        .about("Shows when, how long and by whom each migration was applied"),
     )
     .subcommand(
       Command::new("list")
         .visible_alias("ls")
-        .about("Lists all available migrations"),
+        .about("Lists all available migrations")
+        .arg(format_arg()),
+    )
+    .subcommand(
+      Command::new("wait")
+        .about("Waits until the database accepts connections")
+        .arg(
+          Arg::new("timeout")
+            .long("timeout")
+            .value_name("duration")
+            .help("Gives up once the database was unreachable for this long")
+            .num_args(1)
+            .value_parser(humantime::parse_duration)
+            .default_value("60s"),
+        ),
+    )
+    .subcommand(
+      Command::new("validate")
+        .visible_alias("v")
+        .about("Checks every migration file for problems without connecting to the database"),
+    )
+    .subcommand(
+      Command::new("lint")
+        .about("Flags risky statements in the migrations for the database dialect")
+        .arg(
+          Arg::new("fail_on")
+            .long("fail-on")
+            .help("Exit with an error when a finding has at least this severity")
+            .num_args(1)
+            .default_value("error")
+            .value_parser(["warning", "error"]),
+        ),
     )
     .subcommand(
       Command::new("up")
@@ -313,7 +809,7 @@ fn build_cli(command_name: &str, is_subcommand: bool) -> Command {
           Arg::new("migration_number")
             .help("The migration number to apply up to")
             .num_args(1)
-            .value_parser(clap::value_parser!(usize))
+            .value_parser(clap::value_parser!(i64))
             .required(true),
         ),
     )
@@ -366,11 +862,51 @@ fn build_cli(command_name: &str, is_subcommand: bool) -> Command {
   }
 }
 
+/// Builds the `--format` argument of the status and list subcommands
+fn format_arg() -> Arg {
+  Arg::new("format")
+    .long("format")
+    .value_name("format")
+    .help("Sets the output format")
+    .num_args(1)
+    .value_parser(["table", "json", "yaml"])
+    .default_value("table")
+}
+
+/// Gets the output format of the given subcommand
+fn subcommand_format<'a>(matches: &'a ArgMatches, subcommand: &str) -> AnyhowResult<&'a str> {
+  let format = matches
+    .subcommand_matches(subcommand)
+    .and_then(|m| m.get_one::<String>("format"))
+    .context("Output format was invalid")?;
+
+  Ok(format)
+}
+
+/// Prints a value as a JSON or YAML document
+/// # Arguments
+/// * `value` - The value to print
+/// * `format` - Either `json` or `yaml`
+fn print_document<T: Serialize>(value: &T, format: &str) -> AnyhowResult<()> {
+  match format {
+    "json" => println!("{}", serde_json::to_string_pretty(value)?),
+    "yaml" => print!("{}", serde_yaml::to_string(value)?),
+    _ => anyhow::bail!("Unsupported output format: {format}"),
+  }
+
+  Ok(())
+}
+
 /// Creates a new migration file
 /// This function creates a new migration file with the given slug
 /// # Arguments
 /// * `path` - The migration source directory
+/// * `version` - The version the migration filename starts with
 /// * `slug` - The migration slug
+/// * `template` - The template name, the empty stub is used when omitted
+/// * `table` - The table substituted for `{{table}}`
+/// * `layout` - How the migration is laid out on disk
+/// * `dialect` - The dialect of the built-in templates
 /// # Returns
 /// An `AnyhowResult` indicating the success or failure of the operation
 /// # Errors
@@ -378,19 +914,120 @@ fn build_cli(command_name: &str, is_subcommand: bool) -> Command {
 /// # Example
 /// ```rust
 /// use midas_core::commander::create;
-/// create("migrations", "create_users_table").unwrap();
+/// create("migrations", "0001", "create_users_table", None, None, Layout::Single, Dialect::Sqlite).unwrap();
 /// ```
-fn create(path: &Path, slug: &str) -> AnyhowResult<()> {
+fn create(
+  path: &Path,
+  version: &str,
+  slug: &str,
+  template: Option<&str>,
+  table: Option<&str>,
+  layout: Layout,
+  dialect: Dialect,
+) -> AnyhowResult<()> {
   let fixed_slug = slug.to_ascii_lowercase().replace(' ', "_");
-  lookup::create_migration_file(path, &fixed_slug)?;
+  let template = match template {
+    Some(name) => template::load_template(path, name, dialect)?,
+    None => template::default_template(layout).to_owned(),
+  };
+  lookup::create_migration_file(path, version, &fixed_slug, &template, table, layout)?;
+  Ok(())
+}
+
+/// Validates the migrations directory
+/// This function checks every migration file and prints each problem found
+/// # Arguments
+/// * `path` - The migration source directory
+/// * `version_format` - How migration versions are written
+/// # Errors
+/// This function will return an error if any problem was found
+fn validate(path: &Path, version_format: VersionFormat) -> AnyhowResult<()> {
+  let report = lookup::validate_migrations(path, version_format)?;
+  for issue in &report.issues {
+    let label = style("error:").bold().red();
+    println!("{label} {}: {}", issue.filename, issue.problem);
+  }
+
+  if !report.issues.is_empty() {
+    let mut filenames = report
+      .issues
+      .iter()
+      .map(|issue| &issue.filename)
+      .collect::<Vec<_>>();
+    filenames.dedup();
+    anyhow::bail!(
+      "Found {} problem(s) in {} migration file(s)",
+      report.issues.len(),
+      filenames.len()
+    );
+  }
+
+  println!("All {} migration file(s) are valid.", report.files);
   Ok(())
 }
 
+/// Lints the migration files
+/// This function checks every UP section for risky statements and prints each finding
+/// # Arguments
+/// * `migrations` - The migration files to lint
+/// * `db_url` - The database connection url, only its scheme is used to pick the dialect
+/// * `fail_on` - The severity from which findings make the command fail
+/// # Errors
+/// This function will return an error if any finding reached the threshold
+fn lint(migrations: &MigrationFiles, db_url: &str, fail_on: Severity) -> AnyhowResult<()> {
+  let dialect = dialect_from_url(db_url);
+  let findings = linter::lint_migrations(migrations, dialect);
+  for finding in &findings {
+    let label = match finding.severity {
+      Severity::Warning => style(format!("warning[{}]:", finding.rule)).bold().yellow(),
+      Severity::Error => style(format!("error[{}]:", finding.rule)).bold().red(),
+    };
+    println!(
+      "{label} {}:{}: {}",
+      finding.filename, finding.line, finding.message
+    );
+  }
+
+  let failed = findings
+    .iter()
+    .filter(|finding| finding.severity >= fail_on)
+    .count();
+  if failed > 0 {
+    anyhow::bail!("Found {failed} finding(s) at or above {fail_on} severity");
+  }
+
+  println!(
+    "Linted {} migration file(s), {} finding(s).",
+    migrations.len(),
+    findings.len()
+  );
+  Ok(())
+}
+
+/// Get the SQL dialect of a database connection url without connecting
+fn dialect_from_url(db_url: &str) -> Dialect {
+  match db_url.split_once("://").map(|(scheme, _)| scheme) {
+    Some("postgres") => Dialect::Postgres,
+    Some("mysql") => Dialect::Mysql,
+    _ => Dialect::Sqlite,
+  }
+}
+
+/// A migration file as listed by the list subcommand
+#[derive(Serialize)]
+struct ListEntry<'a> {
+  number: i64,
+  name: &'a str,
+  filename: &'a str,
+}
+
 /// Lists all available migration files
 /// This function lists all the migration files in the migration directory
-/// and prints them to the console in a tabular format
+/// and prints them to the console in a tabular format, or as a JSON or YAML document
 /// # Arguments
 /// * `migrations` - The migration files to list
+/// * `format` - Either `table`, `json` or `yaml`
+/// * `version_format` - How migration versions are displayed
 /// # Returns
 /// An `AnyhowResult` indicating the success or failure of the operation
 /// # Errors
@@ -400,16 +1037,32 @@ fn create(path: &Path, slug: &str) -> AnyhowResult<()> {
 /// use midas_core::lookup::MigrationFiles;
 /// use midas_core::commander::list_migration_files;
 /// let migrations = MigrationFiles::new();
-/// list_migration_files(&migrations).unwrap();
+/// list_migration_files(&migrations, "table", VersionFormat::Timestamp).unwrap();
 /// ```
-fn list_migration_files(migrations: &MigrationFiles) -> AnyhowResult<()> {
+fn list_migration_files(
+  migrations: &MigrationFiles,
+  format: &str,
+  version_format: VersionFormat,
+) -> AnyhowResult<()> {
+  if format != "table" {
+    let entries = migrations
+      .values()
+      .map(|migration| ListEntry {
+        number: migration.number,
+        name: migration.name(),
+        filename: &migration.filename,
+      })
+      .collect::<Vec<_>>();
+    return print_document(&entries, format);
+  }
+
   let mut table = Table::new();
   table.set_titles(row![Fbb->"Migration No.", Fbb->"Filename"]);
   table.set_format(*consts::FORMAT_CLEAN);
 
   // Iterate over the migration files and add them to the table
   for (number, migration) in migrations.iter() {
-    let migration_no = format!("{:013}", number);
+    let migration_no = version_format.format(*number);
     let filename = &migration.filename;
 
     table.add_row(row![
@@ -429,6 +1082,194 @@ fn list_migration_files(migrations: &MigrationFiles) -> AnyhowResult<()> {
   Ok(())
 }
 
+/// Prints the migrations plan
+/// This function prints each planned migration number, filename and the exact SQL
+/// that would be sent to the database, in the order it would run
+/// # Arguments
+/// * `plan` - The planned migrations
+/// * `version_format` - How migration versions are displayed
+fn print_plan(plan: &[PlannedMigration], version_format: VersionFormat) {
+  if plan.is_empty() {
+    println!("Nothing to run, migrations are up-to-date.");
+    return;
+  }
+
+  for step in plan {
+    let direction = match step.direction {
+      Direction::Up => style("UP").bold().green(),
+      Direction::Down => style("DOWN").bold().red(),
+    };
+    let transaction = if step.transactional {
+      ""
+    } else {
+      " (no transaction)"
+    };
+    let header = style(format!(
+      "-- {} {}{}",
+      step.version(version_format),
+      step.filename,
+      transaction
+    ))
+    .bold()
+    .cyan();
+
+    println!("{header} [{direction}]");
+    println!("{}", step.body);
+    println!();
+  }
+}
+
+/// Prints the state of every migration file
+/// This function prints the migration files in a tabular format followed by
+/// the number of completed, total, modified and orphaned migrations
+/// # Arguments
+/// * `report` - The status report of the migrator
+/// * `version_format` - How migration versions are displayed
+fn print_status(report: &StatusReport, version_format: VersionFormat) {
+  // If there are no available migrations, print a message and return
+  if report.entries.is_empty() && report.repeatables.is_empty() {
+    println!("There are no available migration files.");
+    return;
+  }
+
+  // Create a new table instance
+  let mut table = Table::new();
+  table.set_titles(row![Fbb->"Migration No.", Fbb->"Status", Fbb->"Filename"]);
+  table.set_format(*consts::FORMAT_CLEAN);
+
+  // Set the status and color based on the state of the migration
+  for entry in &report.entries {
+    let (status, temp_color) = match entry.state {
+      MigrationState::Modified => ("Modified", color::YELLOW),
+      MigrationState::Applied => ("Active", color::GREEN),
+      MigrationState::Pending => ("Inactive", color::RED),
+      MigrationState::Orphaned => ("Orphaned", color::MAGENTA),
+    };
+
+    let migration_no = version_format.format(entry.number);
+    let filename = entry.filename.as_deref().unwrap_or("-");
+    table.add_row(Row::new(vec![
+      Cell::new(&migration_no).with_style(Attr::Bold),
+      Cell::new(status).with_style(Attr::ForegroundColor(temp_color)),
+      Cell::new(filename).with_style(Attr::ForegroundColor(temp_color)),
+    ]));
+  }
+
+  // Print the table
+  let msg = style("Available migrations:").bold().cyan();
+  println!();
+  println!("{msg}");
+  println!();
+  table.printstd();
+  println!();
+
+  // Print the completed migrations count and the available migrations count
+  let completed_migrations = style("Completed migrations:").bold().cyan();
+  let total_migrations = style("Total migrations:").bold().cyan();
+  println!("{completed_migrations}: {}", report.completed());
+  let orphaned_migrations_count = report.count(MigrationState::Orphaned);
+  println!(
+    "{total_migrations}: {}",
+    report.entries.len() - orphaned_migrations_count
+  );
+
+  // Warn about applied migrations whose files were edited afterwards
+  let modified_migrations_count = report.count(MigrationState::Modified);
+  if modified_migrations_count > 0 {
+    let modified = style("Modified migrations:").bold().yellow();
+    println!("{modified}: {modified_migrations_count} (contents changed since they were applied)");
+  }
+
+  // Warn about applied migrations whose files are missing on disk
+  if orphaned_migrations_count > 0 {
+    let orphaned = style("Orphaned migrations:").bold().magenta();
+    println!("{orphaned}: {orphaned_migrations_count} (applied but missing on disk)");
+  }
+
+  // Repeatable migrations have no number, they are listed in the order `up` applies them
+  if report.repeatables.is_empty() {
+    return;
+  }
+
+  let mut table = Table::new();
+  table.set_titles(row![Fbb->"Name", Fbb->"Status", Fbb->"Filename"]);
+  table.set_format(*consts::FORMAT_CLEAN);
+  for entry in &report.repeatables {
+    let (status, temp_color) = match entry.state {
+      RepeatableState::Applied => ("Active", color::GREEN),
+      RepeatableState::Pending => ("Inactive", color::RED),
+      RepeatableState::Outdated => ("Outdated", color::YELLOW),
+    };
+    table.add_row(Row::new(vec![
+      Cell::new(&entry.name).with_style(Attr::Bold),
+      Cell::new(status).with_style(Attr::ForegroundColor(temp_color)),
+      Cell::new(&entry.filename).with_style(Attr::ForegroundColor(temp_color)),
+    ]));
+  }
+
+  let msg = style("Repeatable migrations:").bold().cyan();
+  println!();
+  println!("{msg}");
+  println!();
+  table.printstd();
+  println!();
+}
+
+/// Prints when and how each migration was applied
+/// # Arguments
+/// * `history` - The rows of the migrations table
+/// * `version_format` - How migration versions are displayed
+fn print_history(history: &[HistoryEntry], version_format: VersionFormat) {
+  // If there are no applied migrations, print a message and return
+  if history.is_empty() {
+    println!("There are no applied migrations.");
+    return;
+  }
+
+  // Create a new table instance
+  let mut table = Table::new();
+  table.set_titles(row![
+    Fbb->"Migration No.",
+    Fbb->"Applied At (UTC)",
+    Fbb->"Duration",
+    Fbb->"Applied By",
+    Fbb->"Version",
+    Fbb->"Filename"
+  ]);
+  table.set_format(*consts::FORMAT_CLEAN);
+
+  // Iterate over the applied migrations, older rows may lack the metadata
+  let unknown = "-".to_owned();
+  for HistoryEntry { record, filename } in history {
+    let migration_no = version_format.format(record.migration);
+    let duration = record
+      .duration_ms
+      .map_or_else(|| unknown.clone(), |ms| format!("{ms} ms"));
+    let applied_by = match (&record.applied_by, &record.db_user) {
+      (Some(os_user), Some(db_user)) => format!("{os_user} ({db_user})"),
+      (Some(user), None) | (None, Some(user)) => user.clone(),
+      (None, None) => unknown.clone(),
+    };
+
+    table.add_row(row![
+      b->&migration_no,
+      Fg->record.applied_at.as_ref().unwrap_or(&unknown),
+      duration,
+      applied_by,
+      record.midas_version.as_ref().unwrap_or(&unknown),
+      Fg->filename.as_ref().unwrap_or(&unknown),
+    ]);
+  }
+
+  // Print the table
+  let msg = style("Migrations history:").bold().cyan();
+  println!();
+  println!("{msg}");
+  println!();
+  table.printstd();
+  println!();
+}
+
 /// Initializes the midas project
 /// This function initializes the midas project by creating the migration directory,
 /// the dotenv file, and the sqlite database file
@@ -507,7 +1348,32 @@ fn convert_local_file_path_to_file_scheme(db_url: &str) -> String {
   }
 }
 
-fn get_executor(db_url: &str) -> AnyhowResult<Box<dyn SequelDriver>> {
+/// How connecting to the database is retried while it starts up, and where the migrations are recorded
+struct ConnectOptions {
+  /// How long a single connection attempt may take
+  timeout: Option<Duration>,
+  /// How many times a failed connection is retried
+  retries: u32,
+  /// The table and schema recording the applied migrations
+  tracking: TrackingTable,
+  /// How TLS is negotiated with PostgreSQL, over the `sslmode` of the URL
+  sslmode: Option<SslMode>,
+  /// The CA certificates trusted by PostgreSQL connections, over the `sslrootcert` of the URL
+  sslrootcert: Option<PathBuf>,
+  /// The client certificate of PostgreSQL connections, over the `sslcert` of the URL
+  sslcert: Option<PathBuf>,
+  /// The client certificate key of PostgreSQL connections, over the `sslkey` of the URL
+  sslkey: Option<PathBuf>,
+}
+
+/// Opens a connection to the database without touching the midas schema
+/// Failed connections are retried `retries` times with exponential backoff, until `deadline`
+fn connect(
+  db_url: &str,
+  options: &ConnectOptions,
+  retries: u32,
+  deadline: Option<Instant>,
+) -> AnyhowResult<Box<dyn SequelDriver>> {
   use url::Url;
 
   // Safeguard against empty database URL
@@ -523,16 +1389,76 @@ fn get_executor(db_url: &str) -> AnyhowResult<Box<dyn SequelDriver>> {
   log::trace!("Connecting to database scheme: {}", url.scheme());
 
   // Match the database scheme and create the appropriate driver
+  let timeout = options.timeout;
   let driver: Box<dyn SequelDriver> = match url.scheme() {
-    "file" | "sqlite" | "sqlite3" => Box::new(Sqlite::new(db_url).context("Failed to create Sqlite driver")?),
-    "mysql" => Box::new(Mysql::new(db_url).context("Failed to create Mysql driver")?),
-    "postgres" => Box::new(Postgres::new(db_url).context("Failed to create Postgres driver")?),
+    "file" | "sqlite" | "sqlite3" => Box::new(
+      retry_connect(retries, timeout, deadline, |_| Sqlite::connect(db_url))
+        .context("Failed to create Sqlite driver")?,
+    ),
+    "mysql" => Box::new(
+      retry_connect(retries, timeout, deadline, |timeout| {
+        Mysql::connect(db_url, timeout)
+      })
+      .context("Failed to create Mysql driver")?,
+    ),
+    "postgres" => {
+      // The TLS flags take precedence over the parameters of the URL
+      let mut ssl = SslOptions::from_url(db_url).context("Invalid TLS settings in the database URL")?;
+      ssl.mode = options.sslmode.unwrap_or(ssl.mode);
+      ssl.root_cert = options.sslrootcert.clone().or(ssl.root_cert);
+      ssl.cert = options.sslcert.clone().or(ssl.cert);
+      ssl.key = options.sslkey.clone().or(ssl.key);
+      Box::new(
+        retry_connect(retries, timeout, deadline, |timeout| {
+          Postgres::connect_ssl(db_url, &ssl, timeout)
+        })
+        .context("Failed to create Postgres driver")?,
+      )
+    },
     _ => return Err(anyhow::anyhow!("Unsupported database scheme: {}", url.scheme())),
   };
 
   Ok(driver)
 }
 
+/// Opens a connection to the database and ensures the migrations table
+/// A `read_only` connection leaves the migrations table as it is, for dry runs
+fn get_executor(
+  db_url: &str,
+  options: &ConnectOptions,
+  read_only: bool,
+) -> AnyhowResult<Box<dyn SequelDriver>> {
+  let mut driver = connect(db_url, options, options.retries, None)?;
+  driver.set_tracking_table(options.tracking.clone());
+  if !read_only {
+    driver
+      .ensure_midas_schema()
+      .context("Failed to create the migrations table")?;
+  }
+  Ok(driver)
+}
+
+/// Waits until the database accepts connections
+/// # Arguments
+/// * `db_url` - The database connection URL
+/// * `options` - How long a single connection attempt may take
+/// * `timeout` - How long to keep retrying
+/// # Errors
+/// This function will return an error if the database is still unreachable after `timeout`
+fn wait(db_url: &str, options: &ConnectOptions, timeout: Duration) -> AnyhowResult<()> {
+  let start = Instant::now();
+  connect(db_url, options, u32::MAX, Some(start + timeout)).with_context(|| {
+    format!(
+      "Database was not reachable within {}",
+      humantime::format_duration(timeout)
+    )
+  })?;
+
+  let msg = style("The database is reachable").bold().green();
+  println!("{msg} after {:.2?}", start.elapsed());
+  Ok(())
+}
+
 #[cfg(test)]
 mod tests {
   use url::Url;
@@ -2,6 +2,7 @@
 //! The main entry point for the migration command
 
 mod cli;
+mod reporter;
 
 use anyhow::Result as AnyhowResult;
 
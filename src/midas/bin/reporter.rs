@@ -0,0 +1,275 @@
+//! Reporters rendering the progress of migration runs
+
+use std::io::{
+  self,
+  IsTerminal as _,
+};
+use std::time::{
+  Duration,
+  SystemTime,
+};
+
+use indicatif::{
+  ProgressBar,
+  ProgressStyle,
+};
+use midas_core::commander::{
+  Direction,
+  PlannedMigration,
+};
+use midas_core::error::MidasError;
+use midas_core::lookup::VersionFormat;
+use midas_core::report::{
+  MigrationObserver,
+  RunReport,
+};
+use serde_json::json;
+
+/// How the progress of a migration run is rendered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputMode {
+  /// A spinner for interactive terminals
+  Tty,
+  /// One timestamped line per migration
+  Plain,
+  /// One JSON object per migration
+  Json,
+}
+
+impl OutputMode {
+  /// Use the given mode, or detect it from whether stdout is a terminal
+  pub fn from_arg(value: Option<&str>) -> Self {
+    match value {
+      Some("tty") => Self::Tty,
+      Some("plain") => Self::Plain,
+      Some("json") => Self::Json,
+      _ if io::stdout().is_terminal() => Self::Tty,
+      _ => Self::Plain,
+    }
+  }
+
+  /// Create the reporter for this mode, displaying migration versions in the given format
+  pub fn reporter(self, version_format: VersionFormat) -> Box<dyn MigrationObserver> {
+    match self {
+      Self::Tty => Box::new(TtyReporter::new(version_format)),
+      Self::Plain => Box::new(PlainReporter { version_format }),
+      Self::Json => Box::new(JsonReporter),
+    }
+  }
+
+  /// Print an informational message about the run
+  pub fn message(self, message: &str) {
+    match self {
+      Self::Tty => println!("{message}"),
+      Self::Plain => println!("{} {message}", timestamp()),
+      Self::Json => println!(
+        "{}",
+        json!({ "timestamp": timestamp(), "event": "message", "message": message })
+      ),
+    }
+  }
+}
+
+/// The current time in RFC 3339 format
+fn timestamp() -> String {
+  humantime::format_rfc3339_millis(SystemTime::now()).to_string()
+}
+
+/// Describe what running the migration did
+fn outcome(direction: Direction) -> &'static str {
+  match direction {
+    Direction::Up => "applied",
+    Direction::Down => "reverted",
+  }
+}
+
+/// Shows the progress of a migration run with a spinner
+struct TtyReporter {
+  /// The progress bar of the current run
+  pb: Option<ProgressBar>,
+  /// How migration versions are displayed
+  version_format: VersionFormat,
+}
+
+impl TtyReporter {
+  fn new(version_format: VersionFormat) -> Self {
+    Self {
+      pb: None,
+      version_format,
+    }
+  }
+}
+
+impl MigrationObserver for TtyReporter {
+  fn on_plan(&mut self, plan: &[PlannedMigration]) {
+    // Create a new progress bar instance
+    let pb = ProgressBar::new(plan.len() as u64);
+    let tick_interval = Duration::from_millis(80);
+    pb.set_style(progress_style());
+    pb.enable_steady_tick(tick_interval);
+    self.pb = Some(pb);
+  }
+
+  fn on_migration_start(&mut self, step: &PlannedMigration) {
+    let Some(pb) = &self.pb else {
+      return;
+    };
+
+    // Set the progress bar prefix and message
+    pb.set_prefix(step.version(self.version_format));
+    match step.direction {
+      Direction::Up => pb.set_message(format!("Applying migration: {}", step.name)),
+      Direction::Down => pb.set_message(format!("Undoing migration: {}", step.name)),
+    }
+  }
+
+  fn on_migration_finish(&mut self, _step: &PlannedMigration, _duration: Duration) {
+    if let Some(pb) = &self.pb {
+      pb.inc(1);
+    }
+  }
+
+  fn on_callback(&mut self, callback: &PlannedMigration, duration: Duration) {
+    if let Some(pb) = &self.pb {
+      pb.println(format!(
+        "  Ran callback: {} ({} ms)",
+        callback.filename,
+        duration.as_millis()
+      ));
+    }
+  }
+
+  fn on_migration_retry(&mut self, step: &PlannedMigration, attempt: u32, delay: Duration) {
+    if let Some(pb) = &self.pb {
+      pb.println(format!(
+        "  Lock timeout in {}, retry {attempt} in {} ms",
+        step.filename,
+        delay.as_millis()
+      ));
+    }
+  }
+
+  fn on_migration_failed(&mut self, _step: &PlannedMigration, _error: &MidasError) {
+    if let Some(pb) = self.pb.take() {
+      pb.abandon();
+    }
+  }
+
+  fn on_finish(&mut self, _report: &RunReport) {
+    if let Some(pb) = self.pb.take() {
+      pb.finish();
+    }
+  }
+}
+
+/// Setup the progress style
+fn progress_style() -> ProgressStyle {
+  ProgressStyle::default_bar()
+    .template("{spinner:.green} [{prefix:.bold.dim}] {wide_msg:.cyan/blue} ")
+    .expect("valid progress template")
+    .tick_chars("⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏⦿")
+}
+
+/// Logs one timestamped line per migration
+struct PlainReporter {
+  /// How migration versions are displayed
+  version_format: VersionFormat,
+}
+
+impl MigrationObserver for PlainReporter {
+  fn on_migration_finish(&mut self, step: &PlannedMigration, duration: Duration) {
+    println!(
+      "{} {} {} {} ({} ms)",
+      timestamp(),
+      outcome(step.direction),
+      step.version(self.version_format),
+      step.filename,
+      duration.as_millis()
+    );
+  }
+
+  fn on_migration_failed(&mut self, step: &PlannedMigration, _error: &MidasError) {
+    println!(
+      "{} failed {} {}",
+      timestamp(),
+      step.version(self.version_format),
+      step.filename
+    );
+  }
+
+  fn on_callback(&mut self, callback: &PlannedMigration, duration: Duration) {
+    println!(
+      "{} callback {} ({} ms)",
+      timestamp(),
+      callback.filename,
+      duration.as_millis()
+    );
+  }
+
+  fn on_migration_retry(&mut self, step: &PlannedMigration, attempt: u32, delay: Duration) {
+    println!(
+      "{} retry {} {} attempt {attempt} in {} ms",
+      timestamp(),
+      step.version(self.version_format),
+      step.filename,
+      delay.as_millis()
+    );
+  }
+}
+
+/// Writes one JSON object per migration
+struct JsonReporter;
+
+impl MigrationObserver for JsonReporter {
+  fn on_migration_finish(&mut self, step: &PlannedMigration, duration: Duration) {
+    let event = json!({
+      "timestamp": timestamp(),
+      "event": "migration",
+      "status": outcome(step.direction),
+      "number": step.number,
+      "repeatable": step.is_repeatable(),
+      "name": step.name,
+      "filename": step.filename,
+      "duration_ms": duration.as_millis() as u64,
+    });
+    println!("{event}");
+  }
+
+  fn on_migration_failed(&mut self, step: &PlannedMigration, error: &MidasError) {
+    let event = json!({
+      "timestamp": timestamp(),
+      "event": "migration",
+      "status": "failed",
+      "number": step.number,
+      "repeatable": step.is_repeatable(),
+      "name": step.name,
+      "filename": step.filename,
+      "error": error.to_string(),
+    });
+    println!("{event}");
+  }
+
+  fn on_callback(&mut self, callback: &PlannedMigration, duration: Duration) {
+    let event = json!({
+      "timestamp": timestamp(),
+      "event": "callback",
+      "name": callback.name,
+      "filename": callback.filename,
+      "duration_ms": duration.as_millis() as u64,
+    });
+    println!("{event}");
+  }
+
+  fn on_migration_retry(&mut self, step: &PlannedMigration, attempt: u32, delay: Duration) {
+    let event = json!({
+      "timestamp": timestamp(),
+      "event": "retry",
+      "number": step.number,
+      "name": step.name,
+      "filename": step.filename,
+      "attempt": attempt,
+      "delay_ms": delay.as_millis() as u64,
+    });
+    println!("{event}");
+  }
+}
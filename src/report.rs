@@ -0,0 +1,189 @@
+//! Structured results of the migrator
+//!
+//! The migrator never prints, it returns these reports and announces progress
+//! through a [`MigrationObserver`] so the caller decides how to render them.
+
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::commander::{
+  Direction,
+  PlannedMigration,
+};
+use crate::error::MidasError;
+use crate::sequel::MigrationRecord;
+
+/// The state of a migration file against the migrations table
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MigrationState {
+  /// Applied and unchanged since
+  Applied,
+  /// Not applied yet
+  Pending,
+  /// Applied, but the file contents changed since
+  Modified,
+  /// Applied, but the migration file is missing on disk
+  Orphaned,
+}
+
+/// A migration and its state
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StatusEntry {
+  /// The migration number
+  pub number: i64,
+  /// The migration name without the number and extension, unless the file is missing
+  pub name: Option<String>,
+  /// The migration filename, unless the file is missing
+  pub filename: Option<String>,
+  /// The state of the migration
+  pub state: MigrationState,
+  /// When the migration was applied (UTC, `YYYY-MM-DD HH:MM:SS`), if known
+  pub applied_at: Option<String>,
+}
+
+/// The state of a repeatable migration file against its last applied version
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RepeatableState {
+  /// Applied and unchanged since
+  Applied,
+  /// Never applied
+  Pending,
+  /// Applied, but the file contents changed since, `up` applies it again
+  Outdated,
+}
+
+/// A repeatable migration and its state
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RepeatableEntry {
+  /// The migration name without the prefix and extension
+  pub name: String,
+  /// The migration filename
+  pub filename: String,
+  /// The state of the migration
+  pub state: RepeatableState,
+  /// When the migration was last applied (UTC, `YYYY-MM-DD HH:MM:SS`), if ever
+  pub applied_at: Option<String>,
+}
+
+/// The state of every migration file and applied migration, in migration number order
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct StatusReport {
+  #[serde(rename = "migrations")]
+  pub entries: Vec<StatusEntry>,
+  /// The repeatable migrations by name
+  pub repeatables: Vec<RepeatableEntry>,
+}
+
+impl StatusReport {
+  /// Count the migrations in the given state
+  pub fn count(&self, state: MigrationState) -> usize {
+    self.entries.iter().filter(|entry| entry.state == state).count()
+  }
+
+  /// Count the migrations recorded in the migrations table
+  pub fn completed(&self) -> usize {
+    self.count(MigrationState::Applied)
+      + self.count(MigrationState::Modified)
+      + self.count(MigrationState::Orphaned)
+  }
+}
+
+/// A row of the migrations table along with its migration file
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+  /// The row of the migrations table
+  pub record: MigrationRecord,
+  /// The migration filename, if the file still exists
+  pub filename: Option<String>,
+}
+
+/// A migration that ran successfully
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationOutcome {
+  /// The migration number
+  pub number: i64,
+  /// The migration name without the number and extension
+  pub name: String,
+  /// The migration filename
+  pub filename: String,
+  /// Whether the migration was applied or undone
+  pub direction: Direction,
+  /// How long the migration and its bookkeeping took
+  pub duration: Duration,
+}
+
+/// The migrations that ran, in the order they ran
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunReport {
+  pub migrations: Vec<MigrationOutcome>,
+}
+
+impl RunReport {
+  /// Whether nothing had to run
+  pub fn is_empty(&self) -> bool {
+    self.migrations.is_empty()
+  }
+}
+
+/// Receives the progress of a migration run, every method does nothing by default
+pub trait MigrationObserver {
+  /// Called once with every migration about to run
+  fn on_plan(&mut self, _plan: &[PlannedMigration]) {}
+
+  /// Called before a migration runs
+  fn on_migration_start(&mut self, _step: &PlannedMigration) {}
+
+  /// Called after a migration and its bookkeeping ran
+  fn on_migration_finish(&mut self, _step: &PlannedMigration, _duration: Duration) {}
+
+  /// Called when a migration failed, the run stops afterwards
+  fn on_migration_failed(&mut self, _step: &PlannedMigration, _error: &MidasError) {}
+
+  /// Called after a callback script ran
+  fn on_callback(&mut self, _callback: &PlannedMigration, _duration: Duration) {}
+
+  /// Called when a migration timed out waiting for a lock and runs again after `delay`
+  fn on_migration_retry(&mut self, _step: &PlannedMigration, _attempt: u32, _delay: Duration) {}
+
+  /// Called once after every migration ran
+  fn on_finish(&mut self, _report: &RunReport) {}
+}
+
+impl<O: MigrationObserver + ?Sized> MigrationObserver for Box<O> {
+  fn on_plan(&mut self, plan: &[PlannedMigration]) {
+    (**self).on_plan(plan);
+  }
+
+  fn on_migration_start(&mut self, step: &PlannedMigration) {
+    (**self).on_migration_start(step);
+  }
+
+  fn on_migration_finish(&mut self, step: &PlannedMigration, duration: Duration) {
+    (**self).on_migration_finish(step, duration);
+  }
+
+  fn on_migration_failed(&mut self, step: &PlannedMigration, error: &MidasError) {
+    (**self).on_migration_failed(step, error);
+  }
+
+  fn on_callback(&mut self, callback: &PlannedMigration, duration: Duration) {
+    (**self).on_callback(callback, duration);
+  }
+
+  fn on_migration_retry(&mut self, step: &PlannedMigration, attempt: u32, delay: Duration) {
+    (**self).on_migration_retry(step, attempt, delay);
+  }
+
+  fn on_finish(&mut self, report: &RunReport) {
+    (**self).on_finish(report);
+  }
+}
+
+/// An observer that ignores every event
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopObserver;
+
+impl MigrationObserver for NoopObserver {}
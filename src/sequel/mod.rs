@@ -1,4 +1,21 @@
-use anyhow::Result as AnyhowResult;
+use std::collections::BTreeMap;
+use std::sync::mpsc;
+use std::time::{
+  Duration,
+  Instant,
+};
+use std::{
+  error,
+  fmt,
+  thread,
+};
+
+use crate::error::{
+  MidasError,
+  Result as MidasResult,
+};
+
+use crate::splitter::Statement;
 
 pub mod mysql;
 pub mod postgres;
@@ -6,16 +23,485 @@ pub mod sqlite;
 
 pub type VecSerial = Vec<i64>;
 
+/// How often a busy migration lock is polled
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The delay before the first connection retry, doubled after every failed attempt
+const CONNECT_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// The longest delay between two connection attempts
+const MAX_CONNECT_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// The default name of the table recording the applied migrations
+pub const DEFAULT_MIGRATIONS_TABLE: &str = "__schema_migrations";
+
+/// The name of the table recording the repeatable migrations next to the default migrations table
+const DEFAULT_REPEATABLES_TABLE: &str = "__schema_repeatables";
+
+/// A map of applied migration numbers to their recorded checksum
+pub type ChecksumMap = BTreeMap<i64, String>;
+
+/// The details stored when a migration is applied
+#[derive(Debug, Clone)]
+pub struct AppliedMigration<'a> {
+  /// The migration number
+  pub migration_number: i64,
+  /// The checksum of the migration file
+  pub checksum: &'a str,
+  /// How long the migration took to run in milliseconds
+  pub duration_ms: i64,
+  /// The operating system user running midas
+  pub applied_by: &'a str,
+  /// The midas version that applied the migration
+  pub midas_version: &'a str,
+}
+
+/// A row of the migrations table, columns added by later versions are empty on older rows
+#[derive(Debug, Clone, Default)]
+pub struct MigrationRecord {
+  /// The migration number
+  pub migration: i64,
+  /// The checksum of the migration file
+  pub checksum: Option<String>,
+  /// When the migration was applied (UTC, `YYYY-MM-DD HH:MM:SS`)
+  pub applied_at: Option<String>,
+  /// How long the migration took to run in milliseconds
+  pub duration_ms: Option<i64>,
+  /// The operating system user that ran midas
+  pub applied_by: Option<String>,
+  /// The database user that ran the migration
+  pub db_user: Option<String>,
+  /// The midas version that applied the migration
+  pub midas_version: Option<String>,
+}
+
+/// The details stored when a repeatable migration is applied
+#[derive(Debug, Clone)]
+pub struct AppliedRepeatable<'a> {
+  /// The repeatable migration name
+  pub name: &'a str,
+  /// The checksum of the repeatable migration file
+  pub checksum: &'a str,
+  /// How long the migration took to run in milliseconds
+  pub duration_ms: i64,
+  /// The operating system user running midas
+  pub applied_by: &'a str,
+  /// The midas version that applied the migration
+  pub midas_version: &'a str,
+}
+
+/// A row of the repeatable migrations table
+#[derive(Debug, Clone, Default)]
+pub struct RepeatableRecord {
+  /// The repeatable migration name
+  pub name: String,
+  /// The checksum of the file the last time it was applied
+  pub checksum: String,
+  /// When the migration was last applied (UTC, `YYYY-MM-DD HH:MM:SS`)
+  pub applied_at: Option<String>,
+}
+
+/// The statement and lock timeouts applied while a migration runs, unset ones use the server default
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timeouts {
+  /// Cancel statements running for longer than this
+  pub statement: Option<Duration>,
+  /// Give up waiting for a table or row lock after this
+  pub lock: Option<Duration>,
+}
+
+impl Timeouts {
+  /// Whether neither timeout is set
+  pub fn is_empty(&self) -> bool {
+    self.statement.is_none() && self.lock.is_none()
+  }
+
+  /// Fill the unset timeouts from the defaults
+  pub fn or(self, defaults: Self) -> Self {
+    Self {
+      statement: self.statement.or(defaults.statement),
+      lock: self.lock.or(defaults.lock),
+    }
+  }
+}
+
+/// A failure reported by the database while executing a migration
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SqlError {
+  /// The error message reported by the database
+  pub message: String,
+  /// The SQLSTATE code of the error, when the database reports one
+  pub sqlstate: Option<String>,
+  /// The detail of the error, when the database reports one
+  pub detail: Option<String>,
+  /// The hint to fix the error, when the database reports one
+  pub hint: Option<String>,
+  /// The byte offset in the executed query the error points at
+  pub position: Option<usize>,
+  /// The byte offset in the executed query of the statement that failed
+  pub statement_offset: usize,
+  /// The statement that failed, starting at 1
+  pub statement_index: usize,
+  /// The number of statements in the executed query
+  pub statement_count: usize,
+  /// Whether the statement gave up waiting for a lock, such failures can be retried
+  pub lock_timeout: bool,
+}
+
+impl SqlError {
+  /// Create a new error for a failed statement
+  fn new(message: impl Into<String>, index: usize, total: usize, statement: &Statement) -> Self {
+    Self {
+      message: message.into(),
+      statement_offset: statement.offset,
+      statement_index: index + 1,
+      statement_count: total,
+      ..Default::default()
+    }
+  }
+
+  /// Point the error at the first occurrence of `needle` in the statement
+  fn locate(mut self, statement: &Statement, from: usize, needle: &str) -> Self {
+    if needle.is_empty() {
+      self.position = Some(statement.offset + statement.sql.len());
+    } else if let Some(index) = statement.sql.get(from..).and_then(|s| s.find(needle)) {
+      self.position = Some(statement.offset + from + index);
+    }
+    self
+  }
+}
+
+impl fmt::Display for SqlError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.message)
+  }
+}
+
+impl error::Error for SqlError {}
+
+/// The error returned when the migration lock could not be acquired in time
+fn lock_held_error(resource: &str, timeout: Duration) -> MidasError {
+  MidasError::LockTimeout {
+    resource: resource.to_owned(),
+    timeout,
+  }
+}
+
+/// Poll `try_lock` until it succeeds or the timeout elapses
+fn wait_for_lock<F>(resource: &str, timeout: Duration, mut try_lock: F) -> MidasResult<()>
+where
+  F: FnMut() -> MidasResult<bool>,
+{
+  let deadline = Instant::now() + timeout;
+  loop {
+    if try_lock()? {
+      return Ok(());
+    }
+
+    let now = Instant::now();
+    if now >= deadline {
+      return Err(lock_held_error(resource, timeout));
+    }
+
+    log::trace!("Waiting for the migration lock on {resource}");
+    thread::sleep(LOCK_POLL_INTERVAL.min(deadline - now));
+  }
+}
+
+/// Where the applied migrations are recorded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackingTable {
+  /// The name of the migrations table
+  table: String,
+  /// The schema holding the tables, the driver default when unset
+  schema: Option<String>,
+  /// Whether the schema is created when missing
+  create_schema: bool,
+  /// The role granted all privileges on the schema once created
+  grant_to: Option<String>,
+}
+
+impl Default for TrackingTable {
+  fn default() -> Self {
+    Self {
+      table: DEFAULT_MIGRATIONS_TABLE.to_owned(),
+      schema: None,
+      create_schema: false,
+      grant_to: None,
+    }
+  }
+}
+
+impl TrackingTable {
+  /// Record the migrations in `table`, inside `schema` or the default schema of the driver
+  pub fn new(table: &str, schema: Option<&str>) -> MidasResult<Self> {
+    Ok(Self {
+      table: identifier(table)?,
+      schema: schema.map(identifier).transpose()?,
+      ..Self::default()
+    })
+  }
+
+  /// Create the schema when it is missing, granting all privileges on it to `grant_to`
+  pub fn with_schema_creation(mut self, grant_to: Option<&str>) -> MidasResult<Self> {
+    self.create_schema = true;
+    self.grant_to = grant_to.map(identifier).transpose()?;
+    Ok(self)
+  }
+
+  /// Use `schema` when none was set
+  pub fn or_schema(mut self, schema: &str) -> Self {
+    self.schema.get_or_insert_with(|| schema.to_owned());
+    self
+  }
+
+  /// The schema holding the tables
+  pub fn schema(&self) -> Option<&str> {
+    self.schema.as_deref()
+  }
+
+  /// Whether the schema is created when missing
+  pub fn create_schema(&self) -> bool {
+    self.create_schema
+  }
+
+  /// The role granted all privileges on the schema once created
+  pub fn grant_to(&self) -> Option<&str> {
+    self.grant_to.as_deref()
+  }
+
+  /// The unqualified name of the migrations table
+  pub fn table(&self) -> &str {
+    &self.table
+  }
+
+  /// The qualified name of the migrations table
+  pub fn migrations(&self) -> String {
+    self.qualify(&self.table)
+  }
+
+  /// The unqualified name of the repeatable migrations table, `<table>_repeatables` for a custom table
+  pub fn repeatables_table(&self) -> String {
+    if self.table == DEFAULT_MIGRATIONS_TABLE {
+      DEFAULT_REPEATABLES_TABLE.to_owned()
+    } else {
+      format!("{}_repeatables", self.table)
+    }
+  }
+
+  /// The qualified name of the repeatable migrations table
+  pub fn repeatables(&self) -> String {
+    self.qualify(&self.repeatables_table())
+  }
+
+  /// A stable key of the qualified migrations table, so each table is migrated under its own lock
+  pub fn lock_key(&self) -> u64 {
+    // FNV-1a, the hashers of std may change between releases
+    self
+      .migrations()
+      .bytes()
+      .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+      })
+  }
+
+  fn qualify(&self, table: &str) -> String {
+    match &self.schema {
+      Some(schema) => format!("{schema}.{table}"),
+      None => table.to_owned(),
+    }
+  }
+}
+
+/// Whether `columns` holds every one of the `expected` columns, names are compared case-insensitively
+fn has_columns(columns: &[String], expected: &[(&str, &str)]) -> bool {
+  expected
+    .iter()
+    .all(|(column, _)| columns.iter().any(|c| c.eq_ignore_ascii_case(column)))
+}
+
+/// Check that a name can be used unquoted in SQL, it is made of letters, digits and underscores
+fn identifier(name: &str) -> MidasResult<String> {
+  let mut chars = name.chars();
+  let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+    && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+  if valid {
+    Ok(name.to_owned())
+  } else {
+    Err(MidasError::InvalidIdentifier(name.to_owned()))
+  }
+}
+
+/// Open a connection, retrying connection failures with exponential backoff
+/// Gives up after `retries` retries or once `deadline` passed, returning the last error.
+/// Each attempt is given `timeout`, shortened to the time left before `deadline`.
+/// Other errors, such as an invalid URL, are returned right away
+pub fn retry_connect<T, F>(
+  retries: u32,
+  timeout: Option<Duration>,
+  deadline: Option<Instant>,
+  mut connect: F,
+) -> MidasResult<T>
+where
+  F: FnMut(Option<Duration>) -> MidasResult<T>,
+{
+  let mut delay = CONNECT_RETRY_DELAY;
+  let mut attempt = 0;
+  loop {
+    // A zero timeout would fail right away, the deadline is only overrun by a millisecond at most
+    let remaining = deadline.map(|deadline| {
+      deadline
+        .saturating_duration_since(Instant::now())
+        .max(Duration::from_millis(1))
+    });
+    let timeout = match (timeout, remaining) {
+      (Some(timeout), Some(remaining)) => Some(timeout.min(remaining)),
+      (timeout, remaining) => timeout.or(remaining),
+    };
+
+    match connect(timeout) {
+      Err(MidasError::Connection(err))
+        if attempt < retries && deadline.map_or(true, |deadline| Instant::now() < deadline) =>
+      {
+        attempt += 1;
+        let delay = deadline.map_or(delay, |deadline| {
+          delay.min(deadline.saturating_duration_since(Instant::now()))
+        });
+        log::warn!("Failed to connect to the database, retry {attempt} in {delay:?}: {err}");
+        thread::sleep(delay);
+      },
+      result => return result,
+    }
+    delay = (delay * 2).min(MAX_CONNECT_RETRY_DELAY);
+  }
+}
+
+/// Run a blocking connect on its own thread, giving up once `timeout` elapsed
+/// The connect timeouts of the drivers only cover the TCP connect, a server that accepts the connection
+/// but never answers would otherwise block the handshake forever. The abandoned thread is left behind.
+fn connect_within<T, F>(timeout: Option<Duration>, connect: F) -> MidasResult<T>
+where
+  T: Send + 'static,
+  F: FnOnce() -> MidasResult<T> + Send + 'static,
+{
+  let Some(timeout) = timeout else {
+    return connect();
+  };
+
+  let (sender, receiver) = mpsc::channel();
+  thread::spawn(move || sender.send(connect()));
+  receiver
+    .recv_timeout(timeout)
+    .map_err(|_| MidasError::Connection(format!("The database did not answer within {timeout:?}").into()))?
+}
+
 pub trait Driver {
-  fn ensure_midas_schema(&mut self) -> AnyhowResult<()>;
-  fn drop_migration_table(&mut self) -> AnyhowResult<()>;
-  fn drop_database(&mut self, db_name: &str) -> AnyhowResult<()>;
-  fn count_migrations(&mut self) -> AnyhowResult<i64>;
-  fn get_completed_migrations(&mut self) -> AnyhowResult<VecSerial>;
-  fn get_last_completed_migration(&mut self) -> AnyhowResult<i64>;
-  fn add_completed_migration(&mut self, migration_number: i64) -> AnyhowResult<()>;
-  fn delete_completed_migration(&mut self, migration_number: i64) -> AnyhowResult<()>;
-  fn delete_last_completed_migration(&mut self) -> AnyhowResult<()>;
-  fn migrate(&mut self, query: &str, migration_number: i64) -> AnyhowResult<()>;
+  /// Whether schema changes can be rolled back inside a transaction
+  fn supports_transactional_ddl(&self) -> bool;
+  fn begin(&mut self) -> MidasResult<()>;
+  fn commit(&mut self) -> MidasResult<()>;
+  fn rollback(&mut self) -> MidasResult<()>;
+  /// Acquire the cross-process migration lock, waiting up to `timeout` for other migrators
+  fn acquire_lock(&mut self, timeout: Duration) -> MidasResult<()>;
+  fn release_lock(&mut self) -> MidasResult<()>;
+  /// Record the migrations in another table or schema, set before the migrations table is ensured
+  fn set_tracking_table(&mut self, tracking: TrackingTable);
+  fn ensure_midas_schema(&mut self) -> MidasResult<()>;
+  /// Whether the migrations table exists, without creating it
+  fn has_migrations_table(&mut self) -> MidasResult<bool>;
+  /// Whether the migrations table has the columns added after its first release, without adding them
+  fn has_upgrade_columns(&mut self) -> MidasResult<bool>;
+  /// Whether the repeatable migrations table exists, without creating it
+  fn has_repeatables_table(&mut self) -> MidasResult<bool>;
+  fn drop_migration_table(&mut self) -> MidasResult<()>;
+  fn drop_database(&mut self, db_name: &str) -> MidasResult<()>;
+  fn count_migrations(&mut self) -> MidasResult<i64>;
+  fn get_completed_migrations(&mut self) -> MidasResult<VecSerial>;
+  fn get_last_completed_migration(&mut self) -> MidasResult<i64>;
+  /// Get the recorded checksums, rows applied before checksums existed are skipped
+  fn get_migration_checksums(&mut self) -> MidasResult<ChecksumMap>;
+  /// Get every row of the migrations table in the order they were applied
+  fn get_migration_history(&mut self) -> MidasResult<Vec<MigrationRecord>>;
+  fn add_completed_migration(&mut self, migration: &AppliedMigration) -> MidasResult<()>;
+  fn delete_completed_migration(&mut self, migration_number: i64) -> MidasResult<()>;
+  fn delete_last_completed_migration(&mut self) -> MidasResult<()>;
+  /// Get the last applied version of every repeatable migration
+  fn get_repeatable_history(&mut self) -> MidasResult<Vec<RepeatableRecord>>;
+  /// Record a repeatable migration, replacing its previous record
+  fn record_repeatable(&mut self, repeatable: &AppliedRepeatable) -> MidasResult<()>;
+  /// Apply the timeouts to the following statements, unset timeouts restore the server default
+  /// Inside a transaction they only last until it ends where the database allows it
+  fn set_timeouts(&mut self, timeouts: &Timeouts) -> MidasResult<()>;
+  /// Execute the statements of a migration, failures point back into `query`
+  fn migrate(&mut self, query: &str, migration_number: i64) -> Result<(), Box<SqlError>>;
   fn db_name(&self) -> &str;
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn it_should_bound_connect_attempts_by_the_deadline() {
+    let start = Instant::now();
+    let deadline = start + Duration::from_millis(700);
+    let mut timeouts = Vec::new();
+    let result = retry_connect::<(), _>(
+      u32::MAX,
+      Some(Duration::from_secs(30)),
+      Some(deadline),
+      |timeout| {
+        timeouts.push(timeout.unwrap());
+        Err(MidasError::Connection("refused".into()))
+      },
+    );
+
+    assert!(matches!(result, Err(MidasError::Connection(_))));
+    assert!(start.elapsed() < Duration::from_secs(1));
+    assert!(timeouts.len() >= 2);
+    assert!(timeouts[0] <= Duration::from_millis(700));
+    assert!(timeouts[1..]
+      .iter()
+      .all(|timeout| *timeout <= Duration::from_millis(200)));
+  }
+
+  #[test]
+  fn it_should_name_the_tracking_tables() {
+    let tracking = TrackingTable::default();
+    assert_eq!(tracking.migrations(), "__schema_migrations");
+    assert_eq!(tracking.repeatables(), "__schema_repeatables");
+    assert_eq!(
+      tracking.or_schema("midas").migrations(),
+      "midas.__schema_migrations"
+    );
+
+    let tracking = TrackingTable::new("billing_migrations", Some("billing"))
+      .unwrap()
+      .or_schema("midas");
+    assert_eq!(tracking.migrations(), "billing.billing_migrations");
+    assert_eq!(tracking.repeatables(), "billing.billing_migrations_repeatables");
+
+    assert!(matches!(
+      TrackingTable::new("migrations; drop table users", None),
+      Err(MidasError::InvalidIdentifier(_))
+    ));
+    assert!(matches!(
+      TrackingTable::default().with_schema_creation(Some("public role")),
+      Err(MidasError::InvalidIdentifier(_))
+    ));
+  }
+
+  #[test]
+  fn it_should_lock_each_tracking_table_separately() {
+    let default = TrackingTable::default().or_schema("midas");
+    let billing = TrackingTable::new("__schema_migrations", Some("billing")).unwrap();
+    let custom = TrackingTable::new("billing_migrations", Some("midas")).unwrap();
+    assert_eq!(
+      default.lock_key(),
+      TrackingTable::default().or_schema("midas").lock_key()
+    );
+    assert_ne!(default.lock_key(), billing.lock_key());
+    assert_ne!(default.lock_key(), custom.lock_key());
+    assert_ne!(billing.lock_key(), custom.lock_key());
+  }
+}
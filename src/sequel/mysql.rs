@@ -1,4 +1,5 @@
-use anyhow::Context as _;
+use std::time::Duration;
+
 use indoc::{
   formatdoc,
   indoc,
@@ -6,16 +7,72 @@ use indoc::{
 use mysql::prelude::Queryable;
 use mysql::{
   params,
+  Opts,
+  OptsBuilder,
   Pool,
   PooledConn,
 };
+use regex::Regex;
+
+use crate::error::DriverContext as _;
+use crate::splitter::{
+  split_statements,
+  Dialect,
+  Statement,
+};
 
 use super::{
-  AnyhowResult,
+  connect_within,
+  has_columns,
+  lock_held_error,
+  AppliedMigration,
+  AppliedRepeatable,
+  ChecksumMap,
   Driver as SequelDriver,
+  MidasError,
+  MidasResult,
+  MigrationRecord,
+  RepeatableRecord,
+  SqlError,
+  Timeouts,
+  TrackingTable,
   VecSerial,
 };
 
+/// The server error code of a lock wait timeout
+const ER_LOCK_WAIT_TIMEOUT: u16 = 1205;
+
+/// Columns added to the migrations table after its first release
+const UPGRADE_COLUMNS: &[(&str, &str)] = &[
+  ("checksum", "VARCHAR(64)"),
+  ("applied_at", "TIMESTAMP NULL"),
+  ("duration_ms", "BIGINT"),
+  ("applied_by", "VARCHAR(255)"),
+  ("db_user", "VARCHAR(255)"),
+  ("midas_version", "VARCHAR(32)"),
+];
+
+/// Point the error at the text quoted by `... near '<text>' at line <n>` messages
+fn locate_near(error: SqlError, statement: &Statement) -> SqlError {
+  let re = Regex::new(r"(?s)near '(?P<near>.*)' at line (?P<line>\d+)$").expect("valid regex");
+  let Some(captures) = re.captures(&error.message) else {
+    return error;
+  };
+
+  let near = captures["near"].to_owned();
+  let line = captures["line"].parse::<usize>().unwrap_or(1);
+  let line_offset = statement
+    .sql
+    .split_inclusive('\n')
+    .take(line.saturating_sub(1))
+    .map(str::len)
+    .sum();
+
+  // Long snippets are truncated by the server, match on the first line only
+  let needle = near.lines().next().unwrap_or_default().to_owned();
+  error.locate(statement, line_offset, &needle)
+}
+
 /// The MySQL struct definition
 pub struct Mysql {
   /// The MySQL connection
@@ -23,58 +80,246 @@ pub struct Mysql {
 
   /// The database name
   database_name: String,
+
+  /// Where the applied migrations are recorded
+  tracking: TrackingTable,
+
+  /// Whether the server is MariaDB, which names some session variables differently
+  mariadb: bool,
 }
 
 /// Implement the MySQL struct
 impl Mysql {
-  pub fn new(database_url: &str) -> AnyhowResult<Self> {
+  pub fn new(database_url: &str) -> MidasResult<Self> {
+    let mut db = Self::connect(database_url, None)?;
+
+    // Ensure the midas schema
+    db.ensure_midas_schema()?;
+    Ok(db)
+  }
+
+  /// Open a connection without touching the midas schema, giving up on the TCP connect after `timeout`
+  pub fn connect(database_url: &str, timeout: Option<Duration>) -> MidasResult<Self> {
     // Open the connection
-    let pool = Pool::new(database_url)?;
-    let conn = pool.get_conn()?;
+    let opts = Opts::from_url(database_url).map_err(|err| MidasError::InvalidUrl(err.to_string()))?;
+    let opts = OptsBuilder::from_opts(opts).tcp_connect_timeout(timeout);
+    let mut conn = connect_within(timeout, move || {
+      let pool = Pool::new(opts).map_err(|err| MidasError::Connection(err.into()))?;
+      pool.get_conn().map_err(|err| MidasError::Connection(err.into()))
+    })?;
 
     // Get the database name from the URL
-    let url = url::Url::parse(database_url)?;
+    let url = url::Url::parse(database_url).map_err(|err| MidasError::InvalidUrl(err.to_string()))?;
     let database_name = url
       .path_segments()
-      .and_then(|s| s.last())
-      .context("Database name not found")?;
+      .and_then(|mut s| s.next_back())
+      .ok_or_else(|| MidasError::InvalidUrl("Database name not found".to_owned()))?;
 
-    // Create a new instance of MySQL
-    let mut db = Mysql {
+    // MariaDB reports itself in the server version
+    let version: Option<String> = conn
+      .query_first("SELECT VERSION()")
+      .map_err(|err| MidasError::Connection(err.into()))?;
+    let mariadb = version.is_some_and(|version| version.contains("MariaDB"));
+
+    Ok(Mysql {
       conn,
       database_name: database_name.into(),
-    };
+      tracking: TrackingTable::default(),
+      mariadb,
+    })
+  }
 
-    // Ensure the midas schema
-    db.ensure_midas_schema()?;
-    Ok(db)
+  /// Whether `table` exists in the schema of the migrations table
+  fn table_exists(&mut self, table: &str) -> MidasResult<bool> {
+    let payload = indoc! {"
+      SELECT COUNT(*) FROM information_schema.tables
+      WHERE table_schema = COALESCE(:schema, DATABASE()) AND table_name = :table
+    "};
+    let count: Option<i64> = self.conn.exec_first(
+      payload,
+      params! {
+        "schema" => self.tracking.schema(),
+        "table" => table,
+      },
+    )?;
+    Ok(count.unwrap_or(0) > 0)
+  }
+
+  /// The columns of the migrations table
+  fn migration_columns(&mut self) -> MidasResult<Vec<String>> {
+    let payload = indoc! {"
+      SELECT column_name FROM information_schema.columns
+      WHERE table_schema = COALESCE(:schema, DATABASE()) AND table_name = :table
+    "};
+    let columns = self.conn.exec(
+      payload,
+      params! {
+        "schema" => self.tracking.schema(),
+        "table" => self.tracking.table(),
+      },
+    )?;
+    Ok(columns)
+  }
+
+  /// The name of the migration lock, the default table keeps the name of older releases
+  fn lock_name(&self) -> String {
+    let tracking = self.tracking.clone().or_schema(&self.database_name);
+    let default = TrackingTable::default().or_schema(&self.database_name);
+    if tracking.migrations() == default.migrations() {
+      format!("midas:{}", self.database_name)
+    } else {
+      // Lock names are limited to 64 characters, too short for a qualified table
+      format!("midas:{:016x}", tracking.lock_key())
+    }
   }
 }
 
 /// Implement the SequelDriver trait for MySQL
 impl SequelDriver for Mysql {
+  /// DDL statements cause an implicit commit on MySQL/MariaDB
+  fn supports_transactional_ddl(&self) -> bool {
+    false
+  }
+
+  /// Start a new transaction
+  fn begin(&mut self) -> MidasResult<()> {
+    self.conn.query_drop("START TRANSACTION")?;
+    Ok(())
+  }
+
+  /// Commit the current transaction
+  fn commit(&mut self) -> MidasResult<()> {
+    self.conn.query_drop("COMMIT")?;
+    Ok(())
+  }
+
+  /// Rollback the current transaction
+  fn rollback(&mut self) -> MidasResult<()> {
+    self.conn.query_drop("ROLLBACK")?;
+    Ok(())
+  }
+
+  /// Acquire the named migration lock, it is released when the connection closes
+  fn acquire_lock(&mut self, timeout: Duration) -> MidasResult<()> {
+    log::trace!("Acquiring the migration named lock");
+    let payload = "SELECT GET_LOCK(:name, :timeout)";
+    let row: Option<Option<i64>> = self.conn.exec_first(
+      payload,
+      params! {
+        "name" => self.lock_name(),
+        "timeout" => timeout.as_secs(),
+      },
+    )?;
+
+    match row.flatten() {
+      Some(1) => Ok(()),
+      Some(_) => Err(lock_held_error(
+        &format!("database {}", self.database_name),
+        timeout,
+      )),
+      None => Err(MidasError::State("Failed to acquire migration lock".to_owned())),
+    }
+  }
+
+  /// Release the named migration lock
+  fn release_lock(&mut self) -> MidasResult<()> {
+    log::trace!("Releasing the migration named lock");
+    let payload = "SELECT RELEASE_LOCK(:name)";
+    self.conn.exec_drop(
+      payload,
+      params! {
+        "name" => self.lock_name(),
+      },
+    )?;
+    Ok(())
+  }
+
+  /// Record the migrations in another table, the schema is a database on MySQL
+  fn set_tracking_table(&mut self, tracking: TrackingTable) {
+    self.tracking = tracking;
+  }
+
   /// Implement the ensure_midas_schema method
-  fn ensure_midas_schema(&mut self) -> AnyhowResult<()> {
-    let payload = indoc! {"
-      CREATE TABLE IF NOT EXISTS __schema_migrations (
+  fn ensure_midas_schema(&mut self) -> MidasResult<()> {
+    if let Some(schema) = self.tracking.schema().filter(|_| self.tracking.create_schema()) {
+      self
+        .conn
+        .query_drop(format!("CREATE DATABASE IF NOT EXISTS {schema}"))?;
+      if let Some(role) = self.tracking.grant_to() {
+        self
+          .conn
+          .query_drop(format!("GRANT ALL ON {schema}.* TO {role}"))?;
+      }
+    }
+
+    let payload = formatdoc! {"
+      CREATE TABLE IF NOT EXISTS {table} (
         id INT NOT NULL AUTO_INCREMENT,
         migration BIGINT,
         PRIMARY KEY (id)
       ) AUTO_INCREMENT = 100;
-    "};
+    ", table = self.tracking.migrations()};
+    self.conn.query_drop(payload)?;
+
+    // Add the missing columns, this also upgrades tables created by older versions in place
+    let columns = self.migration_columns()?;
+    for (column, column_type) in UPGRADE_COLUMNS {
+      if !columns.iter().any(|c| c.eq_ignore_ascii_case(column)) {
+        let payload = format!(
+          "ALTER TABLE {table} ADD COLUMN {column} {column_type}",
+          table = self.tracking.migrations()
+        );
+        self.conn.query_drop(payload)?;
+      }
+    }
+
+    let payload = formatdoc! {"
+      CREATE TABLE IF NOT EXISTS {table} (
+        name VARCHAR(255) NOT NULL,
+        checksum VARCHAR(64) NOT NULL,
+        applied_at TIMESTAMP NULL,
+        duration_ms BIGINT,
+        applied_by VARCHAR(255),
+        midas_version VARCHAR(32),
+        PRIMARY KEY (name)
+      );
+    ", table = self.tracking.repeatables()};
     self.conn.query_drop(payload)?;
     Ok(())
   }
 
-  /// Drop the migration table
-  fn drop_migration_table(&mut self) -> AnyhowResult<()> {
-    let payload = "DROP TABLE __schema_migrations";
+  /// Check whether the migrations table exists
+  fn has_migrations_table(&mut self) -> MidasResult<bool> {
+    let table = self.tracking.table().to_owned();
+    self.table_exists(&table)
+  }
+
+  /// Check whether the migrations table has the columns added after its first release
+  fn has_upgrade_columns(&mut self) -> MidasResult<bool> {
+    let columns = self.migration_columns()?;
+    Ok(has_columns(&columns, UPGRADE_COLUMNS))
+  }
+
+  /// Check whether the repeatable migrations table exists
+  fn has_repeatables_table(&mut self) -> MidasResult<bool> {
+    let table = self.tracking.repeatables_table();
+    self.table_exists(&table)
+  }
+
+  /// Drop the migration tables
+  fn drop_migration_table(&mut self) -> MidasResult<()> {
+    let payload = format!("DROP TABLE {table}", table = self.tracking.migrations());
+    self.conn.query_drop(payload)?;
+    let payload = format!(
+      "DROP TABLE IF EXISTS {table}",
+      table = self.tracking.repeatables()
+    );
     self.conn.query_drop(payload)?;
     Ok(())
   }
 
   /// Drop the database
-  fn drop_database(&mut self, db_name: &str) -> AnyhowResult<()> {
+  fn drop_database(&mut self, db_name: &str) -> MidasResult<()> {
     let payload = formatdoc! {"
       DROP DATABASE IF EXISTS `{db_name}`;
       CREATE DATABASE `{db_name}`;
@@ -84,45 +329,110 @@ impl SequelDriver for Mysql {
   }
 
   /// Count the number of migrations
-  fn count_migrations(&mut self) -> AnyhowResult<i64> {
+  fn count_migrations(&mut self) -> MidasResult<i64> {
     log::trace!("Retrieving migrations count");
-    let payload = "SELECT COUNT(*) as count FROM __schema_migrations";
+    let payload = format!(
+      "SELECT COUNT(*) as count FROM {table}",
+      table = self.tracking.migrations()
+    );
     let row: Option<i64> = self.conn.query_first(payload)?;
     let result = row.unwrap();
     Ok(result)
   }
 
   /// Get all completed migrations
-  fn get_completed_migrations(&mut self) -> AnyhowResult<VecSerial> {
+  fn get_completed_migrations(&mut self) -> MidasResult<VecSerial> {
     log::trace!("Retrieving all completed migrations");
-    let payload = "SELECT migration FROM __schema_migrations ORDER BY id ASC";
+    let payload = format!(
+      "SELECT migration FROM {table} ORDER BY id ASC",
+      table = self.tracking.migrations()
+    );
     let result: VecSerial = self.conn.query(payload)?;
     Ok(result)
   }
 
   /// Get the last completed migration
-  fn get_last_completed_migration(&mut self) -> AnyhowResult<i64> {
+  fn get_last_completed_migration(&mut self) -> MidasResult<i64> {
     log::trace!("Checking and retrieving the last migration stored on migrations table");
-    let payload = "SELECT migration FROM __schema_migrations ORDER BY id DESC LIMIT 1";
+    let payload = format!(
+      "SELECT migration FROM {table} ORDER BY id DESC LIMIT 1",
+      table = self.tracking.migrations()
+    );
     let row: Option<i64> = self.conn.query_first(payload)?;
     let result = row.unwrap();
     Ok(result)
   }
 
+  /// Get the checksums of completed migrations
+  fn get_migration_checksums(&mut self) -> MidasResult<ChecksumMap> {
+    log::trace!("Retrieving checksums of completed migrations");
+    let payload = format!(
+      "SELECT migration, checksum FROM {table} WHERE checksum IS NOT NULL",
+      table = self.tracking.migrations()
+    );
+    let result: Vec<(i64, String)> = self.conn.query(payload)?;
+    Ok(result.into_iter().collect())
+  }
+
+  /// Get the migrations history
+  fn get_migration_history(&mut self) -> MidasResult<Vec<MigrationRecord>> {
+    log::trace!("Retrieving the migrations history");
+    let payload = formatdoc! {"
+      SELECT
+        migration,
+        checksum,
+        DATE_FORMAT(applied_at, '%Y-%m-%d %H:%i:%s'),
+        duration_ms,
+        applied_by,
+        db_user,
+        midas_version
+      FROM {table}
+      ORDER BY id ASC
+    ", table = self.tracking.migrations()};
+    let result = self.conn.query_map(
+      payload,
+      |(migration, checksum, applied_at, duration_ms, applied_by, db_user, midas_version)| MigrationRecord {
+        migration,
+        checksum,
+        applied_at,
+        duration_ms,
+        applied_by,
+        db_user,
+        midas_version,
+      },
+    )?;
+    Ok(result)
+  }
+
   /// Add a completed migration
-  fn add_completed_migration(&mut self, migration_number: i64) -> AnyhowResult<()> {
+  fn add_completed_migration(&mut self, migration: &AppliedMigration) -> MidasResult<()> {
     log::trace!("Adding migration to migrations table");
-    let payload = "INSERT INTO __schema_migrations (migration) VALUES (:migration_number)";
-    self
-      .conn
-      .exec_drop(payload, params! { "migration_number" => migration_number })?;
+    let payload = formatdoc! {"
+      INSERT INTO {table}
+        (migration, checksum, applied_at, duration_ms, applied_by, db_user, midas_version)
+      VALUES
+        (:migration_number, :checksum, CURRENT_TIMESTAMP, :duration_ms, :applied_by, CURRENT_USER(), :midas_version)
+    ", table = self.tracking.migrations()};
+    self.conn.exec_drop(
+      payload,
+      params! {
+        "migration_number" => migration.migration_number,
+        "checksum" => migration.checksum,
+        "duration_ms" => migration.duration_ms,
+        "applied_by" => migration.applied_by,
+        "midas_version" => migration.midas_version,
+      },
+    )?;
     Ok(())
   }
 
   /// Delete a completed migration
-  fn delete_completed_migration(&mut self, migration_number: i64) -> AnyhowResult<()> {
+  fn delete_completed_migration(&mut self, migration_number: i64) -> MidasResult<()> {
     log::trace!("Removing a migration in the migrations table");
-    let payload = "DELETE FROM __schema_migrations WHERE migration = :migration_number";
+    let payload = format!(
+      "DELETE FROM {table} WHERE migration = :migration_number",
+      table = self.tracking.migrations()
+    );
     self
       .conn
       .exec_drop(payload, params! { "migration_number" => migration_number })?;
@@ -130,15 +440,111 @@ impl SequelDriver for Mysql {
   }
 
   /// Delete the last completed migration
-  fn delete_last_completed_migration(&mut self) -> AnyhowResult<()> {
-    let payload = "DELETE FROM __schema_migrations WHERE id=(SELECT MAX(id) FROM __schema_migrations);";
+  fn delete_last_completed_migration(&mut self) -> MidasResult<()> {
+    let payload = format!(
+      "DELETE FROM {table} WHERE id=(SELECT MAX(id) FROM {table});",
+      table = self.tracking.migrations()
+    );
     self.conn.query_drop(payload)?;
     Ok(())
   }
 
+  /// Get the last applied version of every repeatable migration
+  fn get_repeatable_history(&mut self) -> MidasResult<Vec<RepeatableRecord>> {
+    log::trace!("Retrieving the repeatable migrations history");
+    let payload = formatdoc! {"
+      SELECT name, checksum, DATE_FORMAT(applied_at, '%Y-%m-%d %H:%i:%s')
+      FROM {table}
+      ORDER BY name ASC
+    ", table = self.tracking.repeatables()};
+    let result = self
+      .conn
+      .query_map(payload, |(name, checksum, applied_at)| RepeatableRecord {
+        name,
+        checksum,
+        applied_at,
+      })?;
+    Ok(result)
+  }
+
+  /// Record a repeatable migration
+  fn record_repeatable(&mut self, repeatable: &AppliedRepeatable) -> MidasResult<()> {
+    log::trace!("Recording repeatable migration {}", repeatable.name);
+    let payload = formatdoc! {"
+      INSERT INTO {table}
+        (name, checksum, applied_at, duration_ms, applied_by, midas_version)
+      VALUES
+        (:name, :checksum, CURRENT_TIMESTAMP, :duration_ms, :applied_by, :midas_version)
+      ON DUPLICATE KEY UPDATE
+        checksum = VALUES(checksum),
+        applied_at = VALUES(applied_at),
+        duration_ms = VALUES(duration_ms),
+        applied_by = VALUES(applied_by),
+        midas_version = VALUES(midas_version)
+    ", table = self.tracking.repeatables()};
+    self.conn.exec_drop(
+      payload,
+      params! {
+        "name" => repeatable.name,
+        "checksum" => repeatable.checksum,
+        "duration_ms" => repeatable.duration_ms,
+        "applied_by" => repeatable.applied_by,
+        "midas_version" => repeatable.midas_version,
+      },
+    )?;
+    Ok(())
+  }
+
+  /// Set the execution and lock wait timeouts of the session
+  fn set_timeouts(&mut self, timeouts: &Timeouts) -> MidasResult<()> {
+    // max_execution_time is in milliseconds, max_statement_time of MariaDB in seconds with a fraction and
+    // the lock wait timeouts in whole seconds
+    let (statement_setting, statement) = if self.mariadb {
+      let statement = timeouts.statement.map_or_else(
+        || "DEFAULT".to_owned(),
+        |timeout| format!("{:.3}", timeout.as_secs_f64()),
+      );
+      ("max_statement_time", statement)
+    } else {
+      let statement = timeouts
+        .statement
+        .map_or_else(|| "DEFAULT".to_owned(), |timeout| timeout.as_millis().to_string());
+      ("max_execution_time", statement)
+    };
+    let lock = timeouts.lock.map_or_else(
+      || "DEFAULT".to_owned(),
+      |timeout| timeout.as_secs().max(1).to_string(),
+    );
+    for (setting, value) in [
+      (statement_setting, &statement),
+      ("lock_wait_timeout", &lock),
+      ("innodb_lock_wait_timeout", &lock),
+    ] {
+      self
+        .conn
+        .query_drop(format!("SET SESSION {setting} = {value}"))
+        .context(&format!("Failed to set {setting}"))?;
+    }
+    Ok(())
+  }
+
   /// Run a migration
-  fn migrate(&mut self, query: &str, _migration_number: i64) -> AnyhowResult<()> {
-    self.conn.query_drop(query)?;
+  fn migrate(&mut self, query: &str, migration_number: i64) -> Result<(), Box<SqlError>> {
+    let statements = split_statements(query, Dialect::Mysql);
+    for (index, statement) in statements.iter().enumerate() {
+      log::trace!("Running statement {} of migration {migration_number}", index + 1);
+      if let Err(err) = self.conn.query_drop(&statement.sql) {
+        let mut error = SqlError::new(err.to_string(), index, statements.len(), statement);
+        if let mysql::Error::MySqlError(server_error) = err {
+          error.message = server_error.message;
+          error.sqlstate = Some(server_error.state);
+          error.detail = Some(format!("error code {}", server_error.code));
+          error.lock_timeout = server_error.code == ER_LOCK_WAIT_TIMEOUT;
+          error = locate_near(error, statement);
+        }
+        return Err(Box::new(error));
+      }
+    }
     Ok(())
   }
 
@@ -1,39 +1,239 @@
-use anyhow::Context;
-use indoc::indoc;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use indoc::formatdoc;
+use openssl::error::ErrorStack;
+use openssl::ssl::{
+  SslConnector,
+  SslFiletype,
+  SslMethod,
+  SslVerifyMode,
+};
+use postgres::config::SslMode as ConfigSslMode;
+use postgres::error::{
+  ErrorPosition,
+  SqlState,
+};
 use postgres::tls::{
   MakeTlsConnect,
   TlsConnect,
 };
 use postgres::{
   Client,
+  Config,
   NoTls,
   Socket,
 };
+use postgres_openssl::MakeTlsConnector;
 use url::Url;
 
+use crate::error::DriverContext as _;
+use crate::splitter::{
+  split_statements,
+  Dialect,
+};
+
 use super::{
-  AnyhowResult,
+  connect_within,
+  has_columns,
+  wait_for_lock,
+  AppliedMigration,
+  AppliedRepeatable,
+  ChecksumMap,
   Driver as SequelDriver,
+  MidasError,
+  MidasResult,
+  MigrationRecord,
+  RepeatableRecord,
+  SqlError,
+  Timeouts,
+  TrackingTable,
   VecSerial,
 };
 
+/// The advisory lock key held while migrating the default table ("midas" in ASCII)
+const MIGRATION_LOCK_KEY: i64 = 0x6d_69_64_61_73;
+
+/// The schema holding the migrations table when none is set
+const DEFAULT_SCHEMA: &str = "midas";
+
+/// The parameters of the database URL holding the TLS settings
+const SSL_PARAMS: &[&str] = &["sslmode", "sslrootcert", "sslcert", "sslkey"];
+
+/// Columns added to the migrations table after its first release
+const UPGRADE_COLUMNS: &[(&str, &str)] = &[
+  ("checksum", "text"),
+  ("applied_at", "timestamptz"),
+  ("duration_ms", "bigint"),
+  ("applied_by", "text"),
+  ("db_user", "text"),
+  ("midas_version", "text"),
+];
+
+/// How TLS is negotiated with the server, named after the `sslmode` values of libpq
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SslMode {
+  /// Never use TLS
+  Disable,
+  /// Use TLS when the server supports it, without verifying its certificate
+  #[default]
+  Prefer,
+  /// Always use TLS, without verifying the server certificate
+  Require,
+  /// Always use TLS and check that a trusted CA signed the server certificate
+  VerifyCa,
+  /// Like `VerifyCa`, also checking that the server certificate matches the host name
+  VerifyFull,
+}
+
+impl FromStr for SslMode {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "disable" => Ok(Self::Disable),
+      "prefer" => Ok(Self::Prefer),
+      "require" => Ok(Self::Require),
+      "verify-ca" => Ok(Self::VerifyCa),
+      "verify-full" => Ok(Self::VerifyFull),
+      _ => Err(format!("Unknown sslmode: {s}")),
+    }
+  }
+}
+
+/// The TLS settings of a Postgres connection
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SslOptions {
+  /// How TLS is negotiated
+  pub mode: SslMode,
+  /// The CA certificates trusted to sign the server certificate, the OpenSSL defaults when unset
+  pub root_cert: Option<PathBuf>,
+  /// The client certificate presented to the server
+  pub cert: Option<PathBuf>,
+  /// The private key of the client certificate
+  pub key: Option<PathBuf>,
+}
+
+impl SslOptions {
+  /// Read the `sslmode`, `sslrootcert`, `sslcert` and `sslkey` parameters of a database URL
+  pub fn from_url(database_url: &str) -> MidasResult<Self> {
+    let url = Url::parse(database_url).map_err(|err| MidasError::InvalidUrl(err.to_string()))?;
+    let mut options = Self::default();
+    for (name, value) in url.query_pairs() {
+      match &*name {
+        "sslmode" => options.mode = value.parse().map_err(MidasError::InvalidUrl)?,
+        "sslrootcert" => options.root_cert = Some(PathBuf::from(&*value)),
+        "sslcert" => options.cert = Some(PathBuf::from(&*value)),
+        "sslkey" => options.key = Some(PathBuf::from(&*value)),
+        _ => {},
+      }
+    }
+    Ok(options)
+  }
+
+  /// Build the OpenSSL connector negotiating TLS with the server
+  fn connector(&self) -> Result<MakeTlsConnector, ErrorStack> {
+    let mut builder = SslConnector::builder(SslMethod::tls())?;
+    if let Some(root_cert) = &self.root_cert {
+      builder.set_ca_file(root_cert)?;
+    }
+    if let Some(cert) = &self.cert {
+      builder.set_certificate_chain_file(cert)?;
+    }
+    if let Some(key) = &self.key {
+      builder.set_private_key_file(key, SslFiletype::PEM)?;
+    }
+    if !matches!(self.mode, SslMode::VerifyCa | SslMode::VerifyFull) {
+      builder.set_verify(SslVerifyMode::NONE);
+    }
+
+    let mut connector = MakeTlsConnector::new(builder.build());
+    if self.mode == SslMode::VerifyCa {
+      connector.set_callback(|config, _| {
+        config.set_verify_hostname(false);
+        Ok(())
+      });
+    }
+    Ok(connector)
+  }
+}
+
 /// The Postgres struct definition
 pub struct Postgres {
   /// The Postgres client
   client: Client,
   /// The database name
   database_name: String,
+  /// Whether a transaction is open, timeouts are then only set for it
+  in_transaction: bool,
+  /// Where the applied migrations are recorded
+  tracking: TrackingTable,
+  /// Whether the tracking tables live in the default schema because no schema was set
+  default_schema: bool,
 }
 
 /// Implement the Postgres struct
 impl Postgres {
-  /// Create a new instance of Postgres
-  pub fn new(database_url: &str) -> AnyhowResult<Self> {
-    Self::new_tls(database_url, NoTls)
+  /// Create a new instance of Postgres, using TLS as set by the `sslmode` parameter of the URL
+  pub fn new(database_url: &str) -> MidasResult<Self> {
+    let mut db = Self::connect(database_url, None)?;
+
+    // Ensure the midas schema
+    db.ensure_midas_schema()?;
+    Ok(db)
   }
 
   /// Create a new instance of Postgres with TLS
-  pub fn new_tls<T>(database_url: &str, tls_mode: T) -> AnyhowResult<Self>
+  pub fn new_tls<T>(database_url: &str, tls_mode: T) -> MidasResult<Self>
+  where
+    T: MakeTlsConnect<Socket> + 'static + Send,
+    T::TlsConnect: Send,
+    T::Stream: Send,
+    <T::TlsConnect as TlsConnect<Socket>>::Future: Send,
+  {
+    let mut db = Self::connect_tls(database_url, tls_mode, None)?;
+
+    // Ensure the midas schema
+    db.ensure_midas_schema()?;
+    Ok(db)
+  }
+
+  /// Open a connection without touching the midas schema, giving up on it after `timeout`
+  /// TLS is negotiated as set by the `sslmode`, `sslrootcert`, `sslcert` and `sslkey` parameters of the URL
+  pub fn connect(database_url: &str, timeout: Option<Duration>) -> MidasResult<Self> {
+    let ssl = SslOptions::from_url(database_url)?;
+    Self::connect_ssl(database_url, &ssl, timeout)
+  }
+
+  /// Open a connection without touching the midas schema, using the given TLS settings over the URL ones
+  pub fn connect_ssl(database_url: &str, ssl: &SslOptions, timeout: Option<Duration>) -> MidasResult<Self> {
+    if ssl.mode == SslMode::Disable {
+      return Self::open(database_url, NoTls, ssl.mode, timeout);
+    }
+
+    let connector = ssl.connector().map_err(|err| MidasError::Tls(err.into()))?;
+    Self::open(database_url, connector, ssl.mode, timeout)
+  }
+
+  /// Open a connection with TLS without touching the midas schema, giving up on it after `timeout`
+  pub fn connect_tls<T>(database_url: &str, tls_mode: T, timeout: Option<Duration>) -> MidasResult<Self>
+  where
+    T: MakeTlsConnect<Socket> + 'static + Send,
+    T::TlsConnect: Send,
+    T::Stream: Send,
+    <T::TlsConnect as TlsConnect<Socket>>::Future: Send,
+  {
+    let ssl = SslOptions::from_url(database_url)?;
+    Self::open(database_url, tls_mode, ssl.mode, timeout)
+  }
+
+  fn open<T>(
+    database_url: &str,
+    tls_mode: T,
+    ssl_mode: SslMode,
+    timeout: Option<Duration>,
+  ) -> MidasResult<Self>
   where
     T: MakeTlsConnect<Socket> + 'static + Send,
     T::TlsConnect: Send,
@@ -41,48 +241,218 @@ impl Postgres {
     <T::TlsConnect as TlsConnect<Socket>>::Future: Send,
   {
     // Get the database name from the URL
-    let url = Url::parse(database_url)?;
+    let mut url = Url::parse(database_url).map_err(|err| MidasError::InvalidUrl(err.to_string()))?;
     let database_name = url
       .path_segments()
-      .and_then(|s| s.last())
-      .context("Database name not found")?;
+      .and_then(|mut s| s.next_back())
+      .map(ToOwned::to_owned)
+      .ok_or_else(|| MidasError::InvalidUrl("Database name not found".to_owned()))?;
+
+    // The TLS parameters are handled by the connector, the other ones are left to the driver
+    let params = url
+      .query_pairs()
+      .filter(|(name, _)| !SSL_PARAMS.contains(&&**name))
+      .map(|(name, value)| (name.into_owned(), value.into_owned()))
+      .collect::<Vec<_>>();
+    url.set_query(None);
+    if !params.is_empty() {
+      url.query_pairs_mut().extend_pairs(params);
+    }
 
     // Open the connection
-    let client = Client::connect(url.as_str(), tls_mode)?;
+    let mut config = url
+      .as_str()
+      .parse::<Config>()
+      .map_err(|err| MidasError::InvalidUrl(err.to_string()))?;
+    config.ssl_mode(match ssl_mode {
+      SslMode::Disable => ConfigSslMode::Disable,
+      SslMode::Prefer => ConfigSslMode::Prefer,
+      SslMode::Require | SslMode::VerifyCa | SslMode::VerifyFull => ConfigSslMode::Require,
+    });
+    if let Some(timeout) = timeout {
+      config.connect_timeout(timeout);
+    }
+    let client = connect_within(timeout, move || {
+      config
+        .connect(tls_mode)
+        .map_err(|err| MidasError::Connection(err.into()))
+    })?;
 
-    // Create a new instance of Postgres
-    let mut db = Postgres {
+    Ok(Postgres {
       client,
-      database_name: database_name.into(),
-    };
+      database_name,
+      in_transaction: false,
+      tracking: TrackingTable::default().or_schema(DEFAULT_SCHEMA),
+      default_schema: true,
+    })
+  }
 
-    // Ensure the midas schema
-    db.ensure_midas_schema()?;
-    Ok(db)
+  /// Whether the qualified `table` exists, a missing schema means a missing table
+  fn table_exists(&mut self, table: &str) -> MidasResult<bool> {
+    let row = self
+      .client
+      .query_one("select to_regclass($1) is not null", &[&table])?;
+    Ok(row.get(0))
+  }
+
+  /// The advisory lock key of the tracking table, the default table keeps the key of older releases
+  fn lock_key(&self) -> i64 {
+    let default = TrackingTable::default().or_schema(DEFAULT_SCHEMA);
+    if self.tracking.migrations() == default.migrations() {
+      MIGRATION_LOCK_KEY
+    } else {
+      self.tracking.lock_key() as i64
+    }
   }
 }
 
 impl SequelDriver for Postgres {
-  fn ensure_midas_schema(&mut self) -> AnyhowResult<()> {
-    self.client.execute("create schema if not exists midas", &[])?;
-    self.client.execute("grant all on schema midas to public", &[])?;
-    let payload = indoc! {"
-      create table if not exists midas.__schema_migrations (
+  fn supports_transactional_ddl(&self) -> bool {
+    true
+  }
+
+  fn begin(&mut self) -> MidasResult<()> {
+    self
+      .client
+      .batch_execute("begin")
+      .context("Failed to begin transaction")?;
+    self.in_transaction = true;
+    Ok(())
+  }
+
+  fn commit(&mut self) -> MidasResult<()> {
+    self.in_transaction = false;
+    self
+      .client
+      .batch_execute("commit")
+      .context("Failed to commit transaction")?;
+    Ok(())
+  }
+
+  fn rollback(&mut self) -> MidasResult<()> {
+    self.in_transaction = false;
+    self
+      .client
+      .batch_execute("rollback")
+      .context("Failed to rollback transaction")?;
+    Ok(())
+  }
+
+  fn acquire_lock(&mut self, timeout: Duration) -> MidasResult<()> {
+    log::trace!("Acquiring the migration advisory lock");
+    let key = self.lock_key();
+    let client = &mut self.client;
+    let resource = format!("database {}", self.database_name);
+    wait_for_lock(&resource, timeout, || {
+      let row = client
+        .query_one("select pg_try_advisory_lock($1)", &[&key])
+        .context("Failed to acquire migration lock")?;
+      Ok(row.get(0))
+    })
+  }
+
+  fn release_lock(&mut self) -> MidasResult<()> {
+    log::trace!("Releasing the migration advisory lock");
+    self
+      .client
+      .execute("select pg_advisory_unlock($1)", &[&self.lock_key()])
+      .context("Failed to release migration lock")?;
+    Ok(())
+  }
+
+  fn set_tracking_table(&mut self, tracking: TrackingTable) {
+    self.default_schema = tracking.schema().is_none();
+    self.tracking = tracking.or_schema(DEFAULT_SCHEMA);
+  }
+
+  fn ensure_midas_schema(&mut self) -> MidasResult<()> {
+    // The default schema is created when no schema was set, a set schema only when asked to
+    let schema = self.tracking.schema().unwrap_or(DEFAULT_SCHEMA);
+    if self.tracking.create_schema() || self.default_schema {
+      self
+        .client
+        .execute(&format!("create schema if not exists {schema}"), &[])?;
+      if let Some(role) = self.tracking.grant_to() {
+        self
+          .client
+          .execute(&format!("grant all on schema {schema} to {role}"), &[])?;
+      }
+    } else {
+      let payload = "select exists (select 1 from pg_namespace where nspname = $1)";
+      let exists: bool = self.client.query_one(payload, &[&schema])?.get(0);
+      if !exists {
+        return Err(MidasError::State(format!(
+          "The schema {schema} of the migrations table does not exist, create it or enable schema creation"
+        )));
+      }
+    }
+
+    let payload = formatdoc! {"
+      create table if not exists {table} (
         id bigint generated by default as identity primary key,
         migration bigint
       )
-    "};
-    self.client.execute(payload, &[])?;
+    ", table = self.tracking.migrations()};
+    self.client.execute(&payload, &[])?;
+
+    // Add the missing columns, this also upgrades tables created by older versions in place
+    for (column, column_type) in UPGRADE_COLUMNS {
+      let payload = format!(
+        "alter table {table} add column if not exists {column} {column_type}",
+        table = self.tracking.migrations()
+      );
+      self.client.execute(&payload, &[])?;
+    }
+
+    let payload = formatdoc! {"
+      create table if not exists {table} (
+        name text primary key,
+        checksum text not null,
+        applied_at timestamptz,
+        duration_ms bigint,
+        applied_by text,
+        midas_version text
+      )
+    ", table = self.tracking.repeatables()};
+    self.client.execute(&payload, &[])?;
     Ok(())
   }
 
-  fn drop_migration_table(&mut self) -> AnyhowResult<()> {
-    let payload = "drop table midas.__schema_migrations";
-    self.client.execute(payload, &[])?;
+  fn has_migrations_table(&mut self) -> MidasResult<bool> {
+    let table = self.tracking.migrations();
+    self.table_exists(&table)
+  }
+
+  fn has_upgrade_columns(&mut self) -> MidasResult<bool> {
+    let payload = "select column_name::text from information_schema.columns \
+      where table_schema = $1 and table_name = $2";
+    let schema = self.tracking.schema().unwrap_or(DEFAULT_SCHEMA);
+    let columns: Vec<String> = self
+      .client
+      .query(payload, &[&schema, &self.tracking.table()])?
+      .iter()
+      .map(|row| row.get(0))
+      .collect();
+    Ok(has_columns(&columns, UPGRADE_COLUMNS))
+  }
+
+  fn has_repeatables_table(&mut self) -> MidasResult<bool> {
+    let table = self.tracking.repeatables();
+    self.table_exists(&table)
+  }
+
+  fn drop_migration_table(&mut self) -> MidasResult<()> {
+    let payload = format!("drop table {table}", table = self.tracking.migrations());
+    self.client.execute(&payload, &[])?;
+    let payload = format!(
+      "drop table if exists {table}",
+      table = self.tracking.repeatables()
+    );
+    self.client.execute(&payload, &[])?;
     Ok(())
   }
 
-  fn drop_database(&mut self, db_name: &str) -> AnyhowResult<()> {
+  fn drop_database(&mut self, db_name: &str) -> MidasResult<()> {
     let payload = format! {"drop database if exists {db_name}"};
     self.client.execute(&payload, &[])?;
 
@@ -91,29 +461,38 @@ impl SequelDriver for Postgres {
     Ok(())
   }
 
-  fn count_migrations(&mut self) -> AnyhowResult<i64> {
+  fn count_migrations(&mut self) -> MidasResult<i64> {
     log::trace!("Retrieving migrations count");
-    let payload = "select count(*) as count from midas.__schema_migrations";
-    let row = self.client.query_one(payload, &[])?;
+    let payload = format!(
+      "select count(*) as count from {table}",
+      table = self.tracking.migrations()
+    );
+    let row = self.client.query_one(&payload, &[])?;
     let result = row.get::<_, i64>(0);
     Ok(result)
   }
 
-  fn get_completed_migrations(&mut self) -> AnyhowResult<VecSerial> {
+  fn get_completed_migrations(&mut self) -> MidasResult<VecSerial> {
     log::trace!("Retrieving all completed migrations");
-    let payload = "select migration from midas.__schema_migrations order by id asc";
-    let it = self.client.query(payload, &[])?;
+    let payload = format!(
+      "select migration from {table} order by id asc",
+      table = self.tracking.migrations()
+    );
+    let it = self.client.query(&payload, &[])?;
     let result = it.iter().map(|r| r.get("migration")).collect::<_>();
     Ok(result)
   }
 
-  fn get_last_completed_migration(&mut self) -> AnyhowResult<i64> {
+  fn get_last_completed_migration(&mut self) -> MidasResult<i64> {
     log::trace!("Checking and retrieving the last migration stored on migrations table");
-    let payload = "select migration from midas.__schema_migrations order by id desc limit 1";
+    let payload = format!(
+      "select migration from {table} order by id desc limit 1",
+      table = self.tracking.migrations()
+    );
     let result = self
       .client
-      .query(payload, &[])
-      .with_context(|| "Failed to get last completed migration".to_string())?;
+      .query(&payload, &[])
+      .context("Failed to get last completed migration")?;
 
     if result.is_empty() {
       Ok(-1)
@@ -122,41 +501,194 @@ impl SequelDriver for Postgres {
     }
   }
 
-  fn add_completed_migration(&mut self, migration_number: i64) -> AnyhowResult<()> {
+  fn get_migration_checksums(&mut self) -> MidasResult<ChecksumMap> {
+    log::trace!("Retrieving checksums of completed migrations");
+    let payload = format!(
+      "select migration, checksum from {table} where checksum is not null",
+      table = self.tracking.migrations()
+    );
+    let it = self.client.query(&payload, &[])?;
+    let result = it
+      .iter()
+      .map(|r| (r.get("migration"), r.get("checksum")))
+      .collect::<_>();
+    Ok(result)
+  }
+
+  fn get_migration_history(&mut self) -> MidasResult<Vec<MigrationRecord>> {
+    log::trace!("Retrieving the migrations history");
+    let payload = formatdoc! {"
+      select
+        migration,
+        checksum,
+        to_char(applied_at at time zone 'UTC', 'YYYY-MM-DD HH24:MI:SS') as applied_at,
+        duration_ms,
+        applied_by,
+        db_user,
+        midas_version
+      from {table}
+      order by id asc
+    ", table = self.tracking.migrations()};
+    let it = self.client.query(&payload, &[])?;
+    let result = it
+      .iter()
+      .map(|r| MigrationRecord {
+        migration: r.get("migration"),
+        checksum: r.get("checksum"),
+        applied_at: r.get("applied_at"),
+        duration_ms: r.get("duration_ms"),
+        applied_by: r.get("applied_by"),
+        db_user: r.get("db_user"),
+        midas_version: r.get("midas_version"),
+      })
+      .collect::<_>();
+    Ok(result)
+  }
+
+  fn add_completed_migration(&mut self, migration: &AppliedMigration) -> MidasResult<()> {
     log::trace!("Adding migration to migrations table");
-    let payload = "insert into midas.__schema_migrations (migration) values ($1)";
+    let payload = formatdoc! {"
+      insert into {table}
+        (migration, checksum, applied_at, duration_ms, applied_by, db_user, midas_version)
+      values ($1, $2, now(), $3, $4, current_user, $5)
+    ", table = self.tracking.migrations()};
     self
       .client
-      .execute(payload, &[&migration_number])
-      .with_context(|| "Failed to add completed migration".to_string())?;
+      .execute(
+        &payload,
+        &[
+          &migration.migration_number,
+          &migration.checksum,
+          &migration.duration_ms,
+          &migration.applied_by,
+          &migration.midas_version,
+        ],
+      )
+      .context("Failed to add completed migration")?;
     Ok(())
   }
 
-  fn delete_completed_migration(&mut self, migration_number: i64) -> AnyhowResult<()> {
+  fn delete_completed_migration(&mut self, migration_number: i64) -> MidasResult<()> {
     log::trace!("Removing a migration in the migrations table");
-    let payload = "delete from midas.__schema_migrations where migration = $1";
+    let payload = format!(
+      "delete from {table} where migration = $1",
+      table = self.tracking.migrations()
+    );
     self
       .client
-      .execute(payload, &[&migration_number])
-      .with_context(|| "Failed to delete completed migration".to_string())?;
+      .execute(&payload, &[&migration_number])
+      .context("Failed to delete completed migration")?;
     Ok(())
   }
 
-  fn delete_last_completed_migration(&mut self) -> AnyhowResult<()> {
-    let payload =
-      "delete from midas.__schema_migrations where id=(select max(id) from midas.__schema_migrations);";
+  fn delete_last_completed_migration(&mut self) -> MidasResult<()> {
+    let payload = format!(
+      "delete from {table} where id=(select max(id) from {table});",
+      table = self.tracking.migrations()
+    );
     self
       .client
-      .execute(payload, &[])
-      .with_context(|| "Failed to delete last completed migration".to_string())?;
+      .execute(&payload, &[])
+      .context("Failed to delete last completed migration")?;
     Ok(())
   }
 
-  fn migrate(&mut self, query: &str, migration_number: i64) -> AnyhowResult<()> {
+  fn get_repeatable_history(&mut self) -> MidasResult<Vec<RepeatableRecord>> {
+    log::trace!("Retrieving the repeatable migrations history");
+    let payload = formatdoc! {"
+      select
+        name,
+        checksum,
+        to_char(applied_at at time zone 'UTC', 'YYYY-MM-DD HH24:MI:SS') as applied_at
+      from {table}
+      order by name asc
+    ", table = self.tracking.repeatables()};
+    let it = self.client.query(&payload, &[])?;
+    let result = it
+      .iter()
+      .map(|r| RepeatableRecord {
+        name: r.get("name"),
+        checksum: r.get("checksum"),
+        applied_at: r.get("applied_at"),
+      })
+      .collect::<_>();
+    Ok(result)
+  }
+
+  fn record_repeatable(&mut self, repeatable: &AppliedRepeatable) -> MidasResult<()> {
+    log::trace!("Recording repeatable migration {}", repeatable.name);
+    let payload = formatdoc! {"
+      insert into {table}
+        (name, checksum, applied_at, duration_ms, applied_by, midas_version)
+      values ($1, $2, now(), $3, $4, $5)
+      on conflict (name) do update set
+        checksum = excluded.checksum,
+        applied_at = excluded.applied_at,
+        duration_ms = excluded.duration_ms,
+        applied_by = excluded.applied_by,
+        midas_version = excluded.midas_version
+    ", table = self.tracking.repeatables()};
     self
       .client
-      .simple_query(query)
-      .with_context(|| format!("Failed to execute migration - {migration_number}"))?;
+      .execute(
+        &payload,
+        &[
+          &repeatable.name,
+          &repeatable.checksum,
+          &repeatable.duration_ms,
+          &repeatable.applied_by,
+          &repeatable.midas_version,
+        ],
+      )
+      .context("Failed to record repeatable migration")?;
+    Ok(())
+  }
+
+  fn set_timeouts(&mut self, timeouts: &Timeouts) -> MidasResult<()> {
+    // SET LOCAL reverts when the transaction ends, outside of one the session settings are reset
+    let scope = if self.in_transaction { "set local" } else { "set" };
+    for (setting, timeout) in [
+      ("statement_timeout", timeouts.statement),
+      ("lock_timeout", timeouts.lock),
+    ] {
+      let payload = match timeout {
+        Some(timeout) => format!("{scope} {setting} = {}", timeout.as_millis()),
+        None if self.in_transaction => continue,
+        None => format!("reset {setting}"),
+      };
+      self
+        .client
+        .batch_execute(&payload)
+        .context(&format!("Failed to set {setting}"))?;
+    }
+    Ok(())
+  }
+
+  fn migrate(&mut self, query: &str, migration_number: i64) -> Result<(), Box<SqlError>> {
+    let statements = split_statements(query, Dialect::Postgres);
+    for (index, statement) in statements.iter().enumerate() {
+      log::trace!("Running statement {} of migration {migration_number}", index + 1);
+      if let Err(err) = self.client.simple_query(&statement.sql) {
+        let mut error = SqlError::new(err.to_string(), index, statements.len(), statement);
+        if let Some(db_error) = err.as_db_error() {
+          error.message = db_error.message().to_owned();
+          error.sqlstate = Some(db_error.code().code().to_owned());
+          error.lock_timeout = *db_error.code() == SqlState::LOCK_NOT_AVAILABLE;
+          error.detail = db_error.detail().map(ToOwned::to_owned);
+          error.hint = db_error.hint().map(ToOwned::to_owned);
+
+          // The server reports a character position starting at 1
+          if let Some(ErrorPosition::Original(position)) = db_error.position() {
+            error.position = statement
+              .sql
+              .char_indices()
+              .nth((*position as usize).saturating_sub(1))
+              .map(|(offset, _)| statement.offset + offset);
+          }
+        }
+        return Err(Box::new(error));
+      }
+    }
     Ok(())
   }
 
@@ -164,3 +696,28 @@ impl SequelDriver for Postgres {
     &self.database_name
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn it_should_read_ssl_options_from_the_url() {
+    let ssl = SslOptions::from_url(
+      "postgres://app@db.internal/startup?sslmode=verify-full&sslrootcert=/etc/ca.pem&application_name=midas",
+    )
+    .unwrap();
+    assert_eq!(ssl.mode, SslMode::VerifyFull);
+    assert_eq!(ssl.root_cert, Some(PathBuf::from("/etc/ca.pem")));
+    assert_eq!(ssl.cert, None);
+
+    assert_eq!(
+      SslOptions::from_url("postgres://app@db.internal/startup").unwrap(),
+      SslOptions::default()
+    );
+    assert!(matches!(
+      SslOptions::from_url("postgres://app@db.internal/startup?sslmode=always"),
+      Err(MidasError::InvalidUrl(_))
+    ));
+  }
+}
@@ -1,21 +1,84 @@
-use std::fs;
-use std::path::Path;
+use std::fs::{
+  self,
+  OpenOptions,
+};
+use std::io::{
+  ErrorKind,
+  Write as _,
+};
+use std::path::{
+  Path,
+  PathBuf,
+};
+use std::process;
+use std::time::Duration;
 
-use indoc::indoc;
+use indoc::formatdoc;
+use regex::Regex;
 use rusqlite::Connection;
 
+use crate::ensure_migration_state_dir_exists;
+use crate::splitter::{
+  split_statements,
+  Dialect,
+  Statement,
+};
+
 use super::{
-  AnyhowResult,
+  has_columns,
+  wait_for_lock,
+  AppliedMigration,
+  AppliedRepeatable,
+  ChecksumMap,
   Driver as SequelDriver,
+  MidasError,
+  MidasResult,
+  MigrationRecord,
+  RepeatableRecord,
+  SqlError,
+  Timeouts,
+  TrackingTable,
   VecSerial,
 };
 
+/// How long to wait for a locked database by default, the rusqlite default
+const DEFAULT_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Columns added to the migrations table after its first release
+const UPGRADE_COLUMNS: &[(&str, &str)] = &[
+  ("checksum", "TEXT"),
+  ("applied_at", "TEXT"),
+  ("duration_ms", "BIGINT"),
+  ("applied_by", "TEXT"),
+  ("db_user", "TEXT"),
+  ("midas_version", "TEXT"),
+];
+
+/// Point the error at the token named by `near "<token>"` or `no such <object>: <name>` messages
+fn locate_token(error: SqlError, statement: &Statement) -> SqlError {
+  let re = Regex::new(r#"near "(?P<token>[^"]+)"|no such [a-z]+: (?P<name>\S+)"#).expect("valid regex");
+  let Some(captures) = re.captures(&error.message) else {
+    return error;
+  };
+
+  let needle = captures
+    .name("token")
+    .or_else(|| captures.name("name"))
+    .map(|m| m.as_str().to_owned())
+    .unwrap_or_default();
+  error.locate(statement, 0, &needle)
+}
+
 /// The Sqlite struct definition
 pub struct Sqlite {
   /// Implement the Sqlite struct
   conn: Connection,
   /// The file URL of the SQLite database
   file_url: String,
+  /// The lock file held while migrating
+  lock_path: Option<PathBuf>,
+  /// Where the applied migrations are recorded
+  tracking: TrackingTable,
 }
 
 /// Implement the Sqlite struct
@@ -26,10 +89,21 @@ impl Sqlite {
   /// # Returns
   /// * An instance of Sqlite
   /// # Example
-  /// ```
+  /// ```no_run
+  /// use midas_core::sequel::sqlite::Sqlite;
+  ///
   /// let db = Sqlite::new("sqlite://./db.sqlite");
   /// ```
-  pub fn new(file_url: &str) -> AnyhowResult<Self> {
+  pub fn new(file_url: &str) -> MidasResult<Self> {
+    let mut db = Self::connect(file_url)?;
+
+    // Ensure the midas schema migration table exists
+    db.ensure_midas_schema()?;
+    Ok(db)
+  }
+
+  /// Open the database without touching the midas schema
+  pub fn connect(file_url: &str) -> MidasResult<Self> {
     log::trace!("Opening SQLite database connection: {file_url}");
 
     // Strip file:// and file: prefix
@@ -49,42 +123,193 @@ impl Sqlite {
     };
 
     // Open the connection
-    let conn = Connection::open(file_url)?;
-    let mut db: Sqlite = Sqlite {
+    let conn = Connection::open(file_url).map_err(|err| MidasError::Connection(err.into()))?;
+    Ok(Sqlite {
       conn,
       file_url: file_url.to_string(),
-    };
+      lock_path: None,
+      tracking: TrackingTable::default(),
+    })
+  }
 
-    // Ensure the midas schema migration table exists
-    db.ensure_midas_schema()?;
-    Ok(db)
+  /// Whether `table` exists in the schema of the migrations table
+  fn table_exists(&self, table: &str) -> MidasResult<bool> {
+    let payload = "SELECT EXISTS (SELECT 1 FROM pragma_table_info(?1, ?2))";
+    let exists = self.conn.query_row(
+      payload,
+      (table, self.tracking.schema().unwrap_or("main")),
+      |row| row.get(0),
+    )?;
+    Ok(exists)
+  }
+
+  /// The name of the migration lock file, the default table keeps the name of older releases
+  fn lock_file_name(&self, database_file: &str) -> String {
+    let tracking = self.tracking.clone().or_schema("main");
+    let default = TrackingTable::default().or_schema("main");
+    if tracking.migrations() == default.migrations() {
+      format!("{database_file}.lock")
+    } else {
+      format!("{database_file}.{:016x}.lock", tracking.lock_key())
+    }
+  }
+
+  /// The columns of the migrations table
+  fn migration_columns(&self) -> MidasResult<Vec<String>> {
+    let payload = "SELECT name FROM pragma_table_info(?1, ?2)";
+    let mut stmt = self.conn.prepare(payload)?;
+    let columns = stmt
+      .query_map(
+        (self.tracking.table(), self.tracking.schema().unwrap_or("main")),
+        |row| row.get::<_, String>(0),
+      )?
+      .collect::<Result<Vec<_>, _>>()?;
+    Ok(columns)
   }
 }
 
 /// Implement the SequelDriver trait for Sqlite
 impl SequelDriver for Sqlite {
-  /// Ensure the __schema_migrations table exists
+  /// SQLite can rollback schema changes
+  fn supports_transactional_ddl(&self) -> bool {
+    true
+  }
+
+  /// Start a new transaction
+  fn begin(&mut self) -> MidasResult<()> {
+    self.conn.execute_batch("BEGIN")?;
+    Ok(())
+  }
+
+  /// Commit the current transaction
+  fn commit(&mut self) -> MidasResult<()> {
+    self.conn.execute_batch("COMMIT")?;
+    Ok(())
+  }
+
+  /// Rollback the current transaction
+  fn rollback(&mut self) -> MidasResult<()> {
+    self.conn.execute_batch("ROLLBACK")?;
+    Ok(())
+  }
+
+  /// Acquire the migration lock file under the migration state directory
+  fn acquire_lock(&mut self, timeout: Duration) -> MidasResult<()> {
+    ensure_migration_state_dir_exists()?;
+    let database_file = Path::new(&self.file_url)
+      .file_name()
+      .and_then(|s| s.to_str())
+      .unwrap_or("sqlite");
+    let lock_path = Path::new(".migrations-state").join(self.lock_file_name(database_file));
+
+    log::trace!("Acquiring the migration lock file: {:?}", lock_path);
+    let resource = format!("{lock_path:?} (remove it if no other migrator is running)");
+    wait_for_lock(&resource, timeout, || {
+      match OpenOptions::new().write(true).create_new(true).open(&lock_path) {
+        Ok(mut file) => {
+          writeln!(file, "{}", process::id())?;
+          Ok(true)
+        },
+        Err(err) if err.kind() == ErrorKind::AlreadyExists => Ok(false),
+        Err(err) => Err(MidasError::io(
+          format!("Failed to create the lock file {lock_path:?}"),
+          err,
+        )),
+      }
+    })?;
+
+    self.lock_path = Some(lock_path);
+    Ok(())
+  }
+
+  /// Release the migration lock file
+  fn release_lock(&mut self) -> MidasResult<()> {
+    if let Some(lock_path) = self.lock_path.take() {
+      log::trace!("Releasing the migration lock file: {:?}", lock_path);
+      fs::remove_file(&lock_path)
+        .map_err(|err| MidasError::io(format!("Failed to remove the lock file {lock_path:?}"), err))?;
+    }
+    Ok(())
+  }
+
+  /// Record the migrations in another table, the schema is an attached database on SQLite
+  fn set_tracking_table(&mut self, tracking: TrackingTable) {
+    self.tracking = tracking;
+  }
+
+  /// Ensure the migrations table exists
   /// If it doesn't exist, create it
-  fn ensure_midas_schema(&mut self) -> AnyhowResult<()> {
-    let payload = indoc! {"
-      CREATE TABLE IF NOT EXISTS __schema_migrations (
+  fn ensure_midas_schema(&mut self) -> MidasResult<()> {
+    if self.tracking.create_schema() {
+      return Err(MidasError::State(
+        "SQLite can't create schemas, attach the database holding the migrations table instead".to_owned(),
+      ));
+    }
+
+    let payload = formatdoc! {"
+      CREATE TABLE IF NOT EXISTS {table} (
         id INTEGER PRIMARY KEY AUTOINCREMENT,
         migration BIGINT
       );
-    "};
-    self.conn.execute(payload, ())?;
+    ", table = self.tracking.migrations()};
+    self.conn.execute(&payload, ())?;
+
+    // Add the missing columns, this also upgrades tables created by older versions in place
+    let columns = self.migration_columns()?;
+    for (column, column_type) in UPGRADE_COLUMNS {
+      if !columns.iter().any(|c| c.eq_ignore_ascii_case(column)) {
+        let payload = format!(
+          "ALTER TABLE {table} ADD COLUMN {column} {column_type}",
+          table = self.tracking.migrations()
+        );
+        self.conn.execute(&payload, ())?;
+      }
+    }
+
+    let payload = formatdoc! {"
+      CREATE TABLE IF NOT EXISTS {table} (
+        name TEXT PRIMARY KEY,
+        checksum TEXT NOT NULL,
+        applied_at TEXT,
+        duration_ms BIGINT,
+        applied_by TEXT,
+        midas_version TEXT
+      );
+    ", table = self.tracking.repeatables()};
+    self.conn.execute(&payload, ())?;
     Ok(())
   }
 
-  /// Drop the __schema_migrations table
-  fn drop_migration_table(&mut self) -> AnyhowResult<()> {
-    let payload = "DROP TABLE __schema_migrations";
-    self.conn.execute(payload, ())?;
+  /// Check whether the migrations table exists
+  fn has_migrations_table(&mut self) -> MidasResult<bool> {
+    self.table_exists(self.tracking.table())
+  }
+
+  /// Check whether the migrations table has the columns added after its first release
+  fn has_upgrade_columns(&mut self) -> MidasResult<bool> {
+    let columns = self.migration_columns()?;
+    Ok(has_columns(&columns, UPGRADE_COLUMNS))
+  }
+
+  /// Check whether the repeatable migrations table exists
+  fn has_repeatables_table(&mut self) -> MidasResult<bool> {
+    self.table_exists(&self.tracking.repeatables_table())
+  }
+
+  /// Drop the migrations and repeatable migrations tables
+  fn drop_migration_table(&mut self) -> MidasResult<()> {
+    let payload = format!("DROP TABLE {table}", table = self.tracking.migrations());
+    self.conn.execute(&payload, ())?;
+    let payload = format!(
+      "DROP TABLE IF EXISTS {table}",
+      table = self.tracking.repeatables()
+    );
+    self.conn.execute(&payload, ())?;
     Ok(())
   }
 
   /// Drop the database
-  fn drop_database(&mut self, _: &str) -> AnyhowResult<()> {
+  fn drop_database(&mut self, _: &str) -> MidasResult<()> {
     // SQLite does not support dropping databases
     // Instead, we can delete the file
     let path = Path::new(&self.file_url);
@@ -98,59 +323,201 @@ impl SequelDriver for Sqlite {
   }
 
   /// Count the number of migrations
-  fn count_migrations(&mut self) -> AnyhowResult<i64> {
+  fn count_migrations(&mut self) -> MidasResult<i64> {
     log::trace!("Retrieving migrations count");
-    let payload = "SELECT COUNT(*) as count FROM __schema_migrations";
-    let mut stmt = self.conn.prepare(payload)?;
+    let payload = format!(
+      "SELECT COUNT(*) as count FROM {table}",
+      table = self.tracking.migrations()
+    );
+    let mut stmt = self.conn.prepare(&payload)?;
     let result = stmt.query_row((), |row| row.get(0))?;
     Ok(result)
   }
 
   /// Get all completed migrations
-  fn get_completed_migrations(&mut self) -> AnyhowResult<VecSerial> {
+  fn get_completed_migrations(&mut self) -> MidasResult<VecSerial> {
     log::trace!("Retrieving all completed migrations");
-    let payload = "SELECT migration FROM __schema_migrations ORDER BY id ASC";
-    let mut stmt = self.conn.prepare(payload)?;
+    let payload = format!(
+      "SELECT migration FROM {table} ORDER BY id ASC",
+      table = self.tracking.migrations()
+    );
+    let mut stmt = self.conn.prepare(&payload)?;
     let it = stmt.query_map((), |row| row.get(0))?;
     let result = it.map(|r| r.unwrap()).collect::<VecSerial>();
     Ok(result)
   }
 
   /// Get the last completed migration
-  fn get_last_completed_migration(&mut self) -> AnyhowResult<i64> {
+  fn get_last_completed_migration(&mut self) -> MidasResult<i64> {
     log::trace!("Checking and retrieving the last migration stored on migrations table");
-    let payload = "SELECT migration FROM __schema_migrations ORDER BY id DESC LIMIT 1";
-    let mut stmt = self.conn.prepare(payload)?;
+    let payload = format!(
+      "SELECT migration FROM {table} ORDER BY id DESC LIMIT 1",
+      table = self.tracking.migrations()
+    );
+    let mut stmt = self.conn.prepare(&payload)?;
     let result = stmt.query_row((), |row| row.get(0))?;
     Ok(result)
   }
 
+  /// Get the checksums of completed migrations
+  fn get_migration_checksums(&mut self) -> MidasResult<ChecksumMap> {
+    log::trace!("Retrieving checksums of completed migrations");
+    let payload = format!(
+      "SELECT migration, checksum FROM {table} WHERE checksum IS NOT NULL",
+      table = self.tracking.migrations()
+    );
+    let mut stmt = self.conn.prepare(&payload)?;
+    let it = stmt.query_map((), |row| Ok((row.get(0)?, row.get(1)?)))?;
+    let result = it.collect::<Result<ChecksumMap, _>>()?;
+    Ok(result)
+  }
+
+  /// Get the migrations history
+  fn get_migration_history(&mut self) -> MidasResult<Vec<MigrationRecord>> {
+    log::trace!("Retrieving the migrations history");
+    let payload = formatdoc! {"
+      SELECT migration, checksum, applied_at, duration_ms, applied_by, db_user, midas_version
+      FROM {table}
+      ORDER BY id ASC
+    ", table = self.tracking.migrations()};
+    let mut stmt = self.conn.prepare(&payload)?;
+    let it = stmt.query_map((), |row| {
+      Ok(MigrationRecord {
+        migration: row.get(0)?,
+        checksum: row.get(1)?,
+        applied_at: row.get(2)?,
+        duration_ms: row.get(3)?,
+        applied_by: row.get(4)?,
+        db_user: row.get(5)?,
+        midas_version: row.get(6)?,
+      })
+    })?;
+    let result = it.collect::<Result<Vec<_>, _>>()?;
+    Ok(result)
+  }
+
   /// Add a completed migration
-  fn add_completed_migration(&mut self, migration_number: i64) -> AnyhowResult<()> {
+  fn add_completed_migration(&mut self, migration: &AppliedMigration) -> MidasResult<()> {
     log::trace!("Adding migration to migrations table");
-    let payload = "INSERT INTO __schema_migrations (migration) VALUES ($1)";
-    self.conn.execute(payload, [&migration_number])?;
+    let payload = formatdoc! {"
+      INSERT INTO {table}
+        (migration, checksum, applied_at, duration_ms, applied_by, midas_version)
+      VALUES ($1, $2, CURRENT_TIMESTAMP, $3, $4, $5)
+    ", table = self.tracking.migrations()};
+    self.conn.execute(
+      &payload,
+      (
+        &migration.migration_number,
+        migration.checksum,
+        &migration.duration_ms,
+        migration.applied_by,
+        migration.midas_version,
+      ),
+    )?;
     Ok(())
   }
 
   /// Delete a completed migration
-  fn delete_completed_migration(&mut self, migration_number: i64) -> AnyhowResult<()> {
+  fn delete_completed_migration(&mut self, migration_number: i64) -> MidasResult<()> {
     log::trace!("Removing a migration in the migrations table");
-    let payload = "DELETE FROM __schema_migrations WHERE migration = $1";
-    self.conn.execute(payload, [&migration_number])?;
+    let payload = format!(
+      "DELETE FROM {table} WHERE migration = $1",
+      table = self.tracking.migrations()
+    );
+    self.conn.execute(&payload, [&migration_number])?;
     Ok(())
   }
 
   /// Delete the last completed migration
-  fn delete_last_completed_migration(&mut self) -> AnyhowResult<()> {
-    let payload = "DELETE FROM __schema_migrations WHERE id=(SELECT MAX(id) FROM __schema_migrations);";
-    self.conn.execute(payload, ())?;
+  fn delete_last_completed_migration(&mut self) -> MidasResult<()> {
+    let payload = format!(
+      "DELETE FROM {table} WHERE id=(SELECT MAX(id) FROM {table});",
+      table = self.tracking.migrations()
+    );
+    self.conn.execute(&payload, ())?;
+    Ok(())
+  }
+
+  /// Get the last applied version of every repeatable migration
+  fn get_repeatable_history(&mut self) -> MidasResult<Vec<RepeatableRecord>> {
+    log::trace!("Retrieving the repeatable migrations history");
+    let payload = format!(
+      "SELECT name, checksum, applied_at FROM {table} ORDER BY name ASC",
+      table = self.tracking.repeatables()
+    );
+    let mut stmt = self.conn.prepare(&payload)?;
+    let it = stmt.query_map((), |row| {
+      Ok(RepeatableRecord {
+        name: row.get(0)?,
+        checksum: row.get(1)?,
+        applied_at: row.get(2)?,
+      })
+    })?;
+    let result = it.collect::<Result<Vec<_>, _>>()?;
+    Ok(result)
+  }
+
+  /// Record a repeatable migration
+  fn record_repeatable(&mut self, repeatable: &AppliedRepeatable) -> MidasResult<()> {
+    log::trace!("Recording repeatable migration {}", repeatable.name);
+    let payload = formatdoc! {"
+      INSERT INTO {table}
+        (name, checksum, applied_at, duration_ms, applied_by, midas_version)
+      VALUES ($1, $2, CURRENT_TIMESTAMP, $3, $4, $5)
+      ON CONFLICT (name) DO UPDATE SET
+        checksum = excluded.checksum,
+        applied_at = excluded.applied_at,
+        duration_ms = excluded.duration_ms,
+        applied_by = excluded.applied_by,
+        midas_version = excluded.midas_version
+    ", table = self.tracking.repeatables()};
+    self.conn.execute(
+      &payload,
+      (
+        repeatable.name,
+        repeatable.checksum,
+        &repeatable.duration_ms,
+        repeatable.applied_by,
+        repeatable.midas_version,
+      ),
+    )?;
+    Ok(())
+  }
+
+  /// Set how long to wait for a locked database, statements can't be cancelled on SQLite
+  fn set_timeouts(&mut self, timeouts: &Timeouts) -> MidasResult<()> {
+    if timeouts.statement.is_some() {
+      log::warn!("SQLite has no statement timeout, only the lock timeout is applied");
+    }
+    self
+      .conn
+      .busy_timeout(timeouts.lock.unwrap_or(DEFAULT_BUSY_TIMEOUT))?;
     Ok(())
   }
 
   /// Run a migration
-  fn migrate(&mut self, query: &str, _migration_number: i64) -> AnyhowResult<()> {
-    self.conn.execute(query, ())?;
+  fn migrate(&mut self, query: &str, migration_number: i64) -> Result<(), Box<SqlError>> {
+    let statements = split_statements(query, Dialect::Sqlite);
+    for (index, statement) in statements.iter().enumerate() {
+      log::trace!("Running statement {} of migration {migration_number}", index + 1);
+      if let Err(err) = self.conn.execute_batch(&statement.sql) {
+        let mut error = SqlError::new(err.to_string(), index, statements.len(), statement);
+        // The generic SQLITE_ERROR code adds nothing to the message
+        if let rusqlite::Error::SqliteFailure(failure, _) = err {
+          error.lock_timeout = matches!(
+            failure.code,
+            rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked
+          );
+          if failure.code != rusqlite::ErrorCode::Unknown {
+            error.detail = Some(format!(
+              "{:?} (extended code {})",
+              failure.code, failure.extended_code
+            ));
+          }
+        }
+        return Err(Box::new(locate_token(error, statement)));
+      }
+    }
     Ok(())
   }
 
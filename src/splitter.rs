@@ -0,0 +1,358 @@
+//! Dialect-aware SQL statement splitter
+//!
+//! Splits a migration body into the statements the drivers execute one by one.
+//! String literals, quoted identifiers, comments, Postgres dollar-quoted bodies,
+//! MySQL `DELIMITER` blocks and SQLite `BEGIN ... END` trigger bodies are kept intact.
+
+/// The SQL dialect used to split statements
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+  Postgres,
+  Mysql,
+  Sqlite,
+}
+
+/// A single statement found in a migration body
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+  /// The statement text without its delimiter
+  pub sql: String,
+  /// The byte offset of the statement in the source
+  pub offset: usize,
+  /// The line of the statement in the source, starting at 1
+  pub line: usize,
+}
+
+/// The scanner state of the statement being read
+struct Splitter<'a> {
+  source: &'a str,
+  bytes: &'a [u8],
+  dialect: Dialect,
+  delimiter: String,
+  statements: Vec<Statement>,
+  /// Where the current statement starts
+  start: usize,
+  /// Whether the current statement has anything besides whitespace and comments
+  has_content: bool,
+  /// The first keywords of the current statement
+  keywords: Vec<String>,
+  /// The nesting of `BEGIN`/`CASE` ... `END` blocks inside a trigger body
+  block_depth: usize,
+}
+
+impl<'a> Splitter<'a> {
+  fn new(source: &'a str, dialect: Dialect) -> Self {
+    Self {
+      source,
+      bytes: source.as_bytes(),
+      dialect,
+      delimiter: ";".to_owned(),
+      statements: Vec::new(),
+      start: 0,
+      has_content: false,
+      keywords: Vec::new(),
+      block_depth: 0,
+    }
+  }
+
+  /// Run the scanner over the whole source
+  fn split(mut self) -> Vec<Statement> {
+    let mut pos = 0;
+    while pos < self.bytes.len() {
+      pos = self.step(pos);
+    }
+    self.finish_statement(self.bytes.len());
+    self.statements
+  }
+
+  /// Consume the token at `pos` and return the position after it
+  fn step(&mut self, pos: usize) -> usize {
+    let rest = &self.bytes[pos..];
+
+    if self.dialect == Dialect::Mysql && !self.has_content && self.at_line_start(pos) {
+      if let Some(next) = self.delimiter_command(pos) {
+        return next;
+      }
+    }
+
+    if self.block_depth == 0 && rest.starts_with(self.delimiter.as_bytes()) {
+      self.finish_statement(pos);
+      self.start = pos + self.delimiter.len();
+      return self.start;
+    }
+
+    match rest[0] {
+      b'-' if rest.starts_with(b"--") => self.skip_line(pos),
+      b'#' if self.dialect == Dialect::Mysql => self.skip_line(pos),
+      b'/' if rest.starts_with(b"/*") => self.skip_block_comment(pos),
+      b'\'' => self.skip_quoted(pos, b'\'', self.backslash_escapes(pos)),
+      b'"' => self.skip_quoted(pos, b'"', self.dialect == Dialect::Mysql),
+      b'`' if self.dialect != Dialect::Postgres => self.skip_quoted(pos, b'`', false),
+      b'[' if self.dialect == Dialect::Sqlite => self.skip_until(pos, b']'),
+      b'$' if self.dialect == Dialect::Postgres => self.skip_dollar_quoted(pos),
+      c if c.is_ascii_alphabetic() || c == b'_' => self.read_word(pos),
+      c if c.is_ascii_whitespace() => pos + 1,
+      _ => {
+        self.has_content = true;
+        pos + 1
+      },
+    }
+  }
+
+  /// Whether only whitespace precedes `pos` on its line
+  fn at_line_start(&self, pos: usize) -> bool {
+    self.bytes[..pos]
+      .iter()
+      .rev()
+      .take_while(|c| **c != b'\n')
+      .all(u8::is_ascii_whitespace)
+  }
+
+  /// Handle a MySQL client `DELIMITER <token>` line
+  fn delimiter_command(&mut self, pos: usize) -> Option<usize> {
+    let end = self.line_end(pos);
+    let line = self.source[pos..end].trim();
+    let (command, token) = line.split_once(char::is_whitespace)?;
+    if !command.eq_ignore_ascii_case("DELIMITER") {
+      return None;
+    }
+
+    self.delimiter = token.trim().to_owned();
+    self.start = end;
+    Some(end)
+  }
+
+  /// Whether backslashes escape characters in the string literal at `pos`
+  fn backslash_escapes(&self, pos: usize) -> bool {
+    match self.dialect {
+      Dialect::Mysql => true,
+      Dialect::Sqlite => false,
+      // Postgres escape strings are written as E'...'
+      Dialect::Postgres => {
+        pos > 0
+          && self.bytes[pos - 1].eq_ignore_ascii_case(&b'e')
+          && (pos == 1 || !is_word_byte(self.bytes[pos - 2]))
+      },
+    }
+  }
+
+  /// The position of the next newline or the end of the source
+  fn line_end(&self, pos: usize) -> usize {
+    self.bytes[pos..]
+      .iter()
+      .position(|c| *c == b'\n')
+      .map_or(self.bytes.len(), |i| pos + i)
+  }
+
+  fn skip_line(&self, pos: usize) -> usize {
+    self.line_end(pos)
+  }
+
+  fn skip_block_comment(&self, pos: usize) -> usize {
+    // Postgres allows nested block comments
+    let nested = self.dialect == Dialect::Postgres;
+    let mut depth = 0;
+    let mut i = pos;
+    while i < self.bytes.len() {
+      if self.bytes[i..].starts_with(b"/*") && (depth == 0 || nested) {
+        depth += 1;
+        i += 2;
+      } else if self.bytes[i..].starts_with(b"*/") {
+        depth -= 1;
+        i += 2;
+        if depth == 0 {
+          return i;
+        }
+      } else {
+        i += 1;
+      }
+    }
+    self.bytes.len()
+  }
+
+  fn skip_quoted(&mut self, pos: usize, quote: u8, backslash: bool) -> usize {
+    self.has_content = true;
+    let mut i = pos + 1;
+    while i < self.bytes.len() {
+      match self.bytes[i] {
+        b'\\' if backslash => i += 2,
+        // A doubled quote is an escaped quote
+        c if c == quote && self.bytes.get(i + 1) == Some(&quote) => i += 2,
+        c if c == quote => return i + 1,
+        _ => i += 1,
+      }
+    }
+    self.bytes.len()
+  }
+
+  fn skip_until(&mut self, pos: usize, close: u8) -> usize {
+    self.has_content = true;
+    self.bytes[pos + 1..]
+      .iter()
+      .position(|c| *c == close)
+      .map_or(self.bytes.len(), |i| pos + i + 2)
+  }
+
+  fn skip_dollar_quoted(&mut self, pos: usize) -> usize {
+    self.has_content = true;
+
+    // A dollar sign inside an identifier or a positional parameter is not a quote
+    if pos > 0 && is_word_byte(self.bytes[pos - 1]) {
+      return pos + 1;
+    }
+
+    let tag_len = self.bytes[pos + 1..]
+      .iter()
+      .take_while(|c| c.is_ascii_alphanumeric() || **c == b'_')
+      .count();
+    let tag_end = pos + 1 + tag_len;
+    let valid_tag =
+      self.bytes.get(tag_end) == Some(&b'$') && self.bytes.get(pos + 1).map_or(true, |c| !c.is_ascii_digit());
+    if !valid_tag {
+      return pos + 1;
+    }
+
+    let tag = &self.source[pos..=tag_end];
+    self.source[tag_end + 1..]
+      .find(tag)
+      .map_or(self.bytes.len(), |i| tag_end + 1 + i + tag.len())
+  }
+
+  fn read_word(&mut self, pos: usize) -> usize {
+    self.has_content = true;
+    let len = self.bytes[pos..].iter().take_while(|c| is_word_byte(**c)).count();
+    let word = self.source[pos..pos + len].to_ascii_uppercase();
+
+    if self.is_trigger() {
+      match word.as_str() {
+        "BEGIN" | "CASE" => self.block_depth += 1,
+        "END" => self.block_depth = self.block_depth.saturating_sub(1),
+        _ => {},
+      }
+    }
+
+    if self.keywords.len() < 3 {
+      self.keywords.push(word);
+    }
+    pos + len
+  }
+
+  /// Whether the current statement is a SQLite `CREATE TRIGGER`
+  fn is_trigger(&self) -> bool {
+    if self.dialect != Dialect::Sqlite {
+      return false;
+    }
+
+    match self.keywords.as_slice() {
+      [create, trigger, ..] if create == "CREATE" && trigger == "TRIGGER" => true,
+      [create, temp, trigger] => {
+        create == "CREATE" && (temp == "TEMP" || temp == "TEMPORARY") && trigger == "TRIGGER"
+      },
+      _ => false,
+    }
+  }
+
+  fn finish_statement(&mut self, end: usize) {
+    let text = &self.source[self.start..end];
+    let leading = text.len() - text.trim_start().len();
+    let offset = self.start + leading;
+    let sql = text.trim();
+
+    if self.has_content && !sql.is_empty() {
+      self.statements.push(Statement {
+        sql: sql.to_owned(),
+        offset,
+        line: self.source[..offset].matches('\n').count() + 1,
+      });
+    }
+
+    self.has_content = false;
+    self.keywords.clear();
+    self.block_depth = 0;
+  }
+}
+
+fn is_word_byte(c: u8) -> bool {
+  c.is_ascii_alphanumeric() || c == b'_' || c == b'$'
+}
+
+/// Split a migration body into statements for the given dialect
+pub fn split_statements(sql: &str, dialect: Dialect) -> Vec<Statement> {
+  Splitter::new(sql, dialect).split()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn split(sql: &str, dialect: Dialect) -> Vec<String> {
+    split_statements(sql, dialect)
+      .into_iter()
+      .map(|s| s.sql)
+      .collect()
+  }
+
+  #[test]
+  fn it_should_split_simple_statements() {
+    let statements = split_statements(
+      "create table a (id int);\n\ncreate table b (id int);",
+      Dialect::Sqlite,
+    );
+    assert_eq!(statements.len(), 2);
+    assert_eq!(statements[0].sql, "create table a (id int)");
+    assert_eq!(statements[1].sql, "create table b (id int)");
+    assert_eq!(statements[1].line, 3);
+    assert_eq!(statements[1].offset, 26);
+  }
+
+  #[test]
+  fn it_should_ignore_delimiters_in_literals_and_comments() {
+    let sql =
+      "insert into a values ('x;y', \"c;d\"); -- trailing; comment\n/* block; */ select 1;\n-- drop table a;";
+    assert_eq!(
+      split(sql, Dialect::Postgres),
+      vec![
+        "insert into a values ('x;y', \"c;d\")",
+        "-- trailing; comment\n/* block; */ select 1",
+      ]
+    );
+    assert_eq!(
+      split("select 'it''s; fine';", Dialect::Sqlite),
+      vec!["select 'it''s; fine'"]
+    );
+    assert_eq!(
+      split("select 'a\\'; b';", Dialect::Mysql),
+      vec!["select 'a\\'; b'"]
+    );
+  }
+
+  #[test]
+  fn it_should_keep_postgres_dollar_quoted_bodies() {
+    let sql = "create function f() returns int as $$ begin return 1; end; $$ language plpgsql;\n\
+               create function g() returns int as $body$ select $1; $body$ language sql;";
+    let statements = split(sql, Dialect::Postgres);
+    assert_eq!(statements.len(), 2);
+    assert!(statements[0].ends_with("language plpgsql"));
+    assert!(statements[1].contains("$body$ select $1; $body$"));
+  }
+
+  #[test]
+  fn it_should_honour_mysql_delimiter_blocks() {
+    let sql = "DELIMITER //\nCREATE PROCEDURE p() BEGIN SELECT 1; SELECT 2; END//\nDELIMITER ;\nSELECT 3;";
+    assert_eq!(
+      split(sql, Dialect::Mysql),
+      vec!["CREATE PROCEDURE p() BEGIN SELECT 1; SELECT 2; END", "SELECT 3",]
+    );
+  }
+
+  #[test]
+  fn it_should_keep_sqlite_trigger_bodies() {
+    let sql =
+      "CREATE TRIGGER t AFTER INSERT ON a BEGIN\n  UPDATE b SET n = CASE WHEN n > 0 THEN n ELSE 0 END;\n  \
+               DELETE FROM c;\nEND;\nCREATE TABLE d (id INTEGER);";
+    let statements = split(sql, Dialect::Sqlite);
+    assert_eq!(statements.len(), 2);
+    assert!(statements[0].ends_with("END"));
+    assert_eq!(statements[1], "CREATE TABLE d (id INTEGER)");
+  }
+}
@@ -0,0 +1,219 @@
+//! Templates for new migration files
+//!
+//! A template is looked up in the `.templates` directory of the migrations directory
+//! first, then among the built-in templates of the dialect. Placeholders such as
+//! `{{name}}` are replaced when the migration file is created.
+
+use std::fs;
+use std::path::Path;
+
+use indoc::indoc;
+
+use crate::error::{
+  MidasError,
+  Result as MidasResult,
+};
+use crate::lookup::Layout;
+use crate::splitter::Dialect;
+
+/// The directory of the migrations directory holding custom templates
+pub const TEMPLATES_DIR: &str = ".templates";
+
+/// The stub written when no template is given
+pub const DEFAULT_TEMPLATE: &str = indoc! {"\
+  -- # Put your SQL below migration seperator.
+  -- !UP
+
+  -- !DOWN
+"};
+
+/// Get the stub written when no template is given, split layouts start from empty files
+pub fn default_template(layout: Layout) -> &'static str {
+  match layout {
+    Layout::Single => DEFAULT_TEMPLATE,
+    Layout::Paired | Layout::Directory => "-- !UP\n-- !DOWN\n",
+  }
+}
+
+/// The values substituted into a template
+#[derive(Debug, Clone, Copy)]
+pub struct Placeholders<'a> {
+  /// `{{name}}`, the migration slug
+  pub name: &'a str,
+  /// `{{table}}`, the table the migration is about
+  pub table: &'a str,
+  /// `{{timestamp}}`, the migration version
+  pub timestamp: &'a str,
+  /// `{{author}}`, the user creating the migration
+  pub author: &'a str,
+}
+
+/// Replace every placeholder of the template with its value
+pub fn render(template: &str, placeholders: &Placeholders<'_>) -> String {
+  [
+    ("{{name}}", placeholders.name),
+    ("{{table}}", placeholders.table),
+    ("{{timestamp}}", placeholders.timestamp),
+    ("{{author}}", placeholders.author),
+  ]
+  .iter()
+  .fold(template.to_owned(), |text, (placeholder, value)| {
+    text.replace(placeholder, value)
+  })
+}
+
+/// Guess the table from a migration slug, e.g. `users` from `create_users_table` or `add_email_to_users`
+pub fn table_from_slug(slug: &str) -> &str {
+  if let Some((_, table)) = slug.rsplit_once("_to_").or_else(|| slug.rsplit_once("_on_")) {
+    return table;
+  }
+
+  let table = ["create_", "alter_", "drop_", "update_"]
+    .iter()
+    .find_map(|prefix| slug.strip_prefix(prefix))
+    .unwrap_or(slug);
+  table.strip_suffix("_table").unwrap_or(table)
+}
+
+/// Get a built-in template for the dialect
+pub fn builtin(name: &str, dialect: Dialect) -> Option<&'static str> {
+  let template = match (name, dialect) {
+    ("create_table", Dialect::Postgres) => indoc! {"\
+      -- {{name}}, created by {{author}}
+      -- !UP
+      CREATE TABLE {{table}} (
+        id BIGSERIAL PRIMARY KEY,
+        created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+      );
+
+      -- !DOWN
+      DROP TABLE {{table}};
+    "},
+    ("create_table", Dialect::Mysql) => indoc! {"\
+      -- {{name}}, created by {{author}}
+      -- !UP
+      CREATE TABLE {{table}} (
+        id BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+        created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+      );
+
+      -- !DOWN
+      DROP TABLE {{table}};
+    "},
+    ("create_table", Dialect::Sqlite) => indoc! {"\
+      -- {{name}}, created by {{author}}
+      -- !UP
+      CREATE TABLE {{table}} (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+      );
+
+      -- !DOWN
+      DROP TABLE {{table}};
+    "},
+    ("add_column", Dialect::Postgres | Dialect::Sqlite) => indoc! {"\
+      -- {{name}}, created by {{author}}
+      -- !UP
+      ALTER TABLE {{table}} ADD COLUMN new_column TEXT;
+
+      -- !DOWN
+      ALTER TABLE {{table}} DROP COLUMN new_column;
+    "},
+    ("add_column", Dialect::Mysql) => indoc! {"\
+      -- {{name}}, created by {{author}}
+      -- !UP
+      ALTER TABLE {{table}} ADD COLUMN new_column VARCHAR(255) NULL;
+
+      -- !DOWN
+      ALTER TABLE {{table}} DROP COLUMN new_column;
+    "},
+    ("create_index", Dialect::Postgres) => indoc! {"\
+      -- {{name}}, created by {{author}}
+      -- !NO_TRANSACTION
+      -- !UP
+      CREATE INDEX CONCURRENTLY {{table}}_new_column_idx ON {{table}} (new_column);
+
+      -- !DOWN
+      DROP INDEX CONCURRENTLY {{table}}_new_column_idx;
+    "},
+    ("create_index", Dialect::Mysql) => indoc! {"\
+      -- {{name}}, created by {{author}}
+      -- !UP
+      CREATE INDEX {{table}}_new_column_idx ON {{table}} (new_column);
+
+      -- !DOWN
+      DROP INDEX {{table}}_new_column_idx ON {{table}};
+    "},
+    ("create_index", Dialect::Sqlite) => indoc! {"\
+      -- {{name}}, created by {{author}}
+      -- !UP
+      CREATE INDEX {{table}}_new_column_idx ON {{table}} (new_column);
+
+      -- !DOWN
+      DROP INDEX {{table}}_new_column_idx;
+    "},
+    _ => return None,
+  };
+  Some(template)
+}
+
+/// Load a template from the templates directory, falling back to the built-in templates
+pub fn load_template(path: &Path, name: &str, dialect: Dialect) -> MidasResult<String> {
+  let name = name.trim_end_matches(".sql");
+  let filepath = path.join(TEMPLATES_DIR).join(format!("{name}.sql"));
+  if filepath.is_file() {
+    return fs::read_to_string(&filepath)
+      .map_err(|err| MidasError::io(format!("Failed to read the template {filepath:?}"), err));
+  }
+
+  builtin(name, dialect)
+    .map(ToOwned::to_owned)
+    .ok_or_else(|| MidasError::UnknownTemplate(name.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn it_should_render_placeholders() {
+    let placeholders = Placeholders {
+      name: "create_users_table",
+      table: table_from_slug("create_users_table"),
+      timestamp: "1700000000000",
+      author: "jane",
+    };
+    let text = render(
+      "-- {{name}} {{timestamp}} {{author}}\nDROP TABLE {{table}};",
+      &placeholders,
+    );
+    assert_eq!(
+      text,
+      "-- create_users_table 1700000000000 jane\nDROP TABLE users;"
+    );
+
+    assert_eq!(table_from_slug("add_email_to_users"), "users");
+    assert_eq!(table_from_slug("index_email_on_accounts"), "accounts");
+    assert_eq!(table_from_slug("posts"), "posts");
+  }
+
+  #[test]
+  fn it_should_prefer_templates_from_the_migrations_directory() {
+    let dir = assert_fs::TempDir::new().unwrap();
+    let builtin = load_template(dir.path(), "create_index", Dialect::Postgres).unwrap();
+    assert!(builtin.contains("CREATE INDEX CONCURRENTLY"));
+    assert!(matches!(
+      load_template(dir.path(), "seed", Dialect::Sqlite),
+      Err(MidasError::UnknownTemplate(_))
+    ));
+
+    fs::create_dir(dir.path().join(TEMPLATES_DIR)).unwrap();
+    fs::write(
+      dir.path().join(TEMPLATES_DIR).join("create_index.sql"),
+      "-- !UP\n-- !DOWN\n",
+    )
+    .unwrap();
+    let custom = load_template(dir.path(), "create_index", Dialect::Postgres).unwrap();
+    assert_eq!(custom, "-- !UP\n-- !DOWN\n");
+  }
+}
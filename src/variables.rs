@@ -0,0 +1,129 @@
+//! Variable substitution in migration bodies
+//!
+//! `${NAME}` placeholders are replaced before a body is sent to the driver, and `$${`
+//! is written for a literal `${`. Undefined variables are an error rather than left as is.
+
+use std::collections::BTreeMap;
+use std::env;
+
+use crate::error::{
+  MidasError,
+  Result as MidasResult,
+};
+
+/// The values of the `${NAME}` placeholders of migration bodies
+#[derive(Debug, Clone, Default)]
+pub struct Variables {
+  /// Values set explicitly, taking precedence over the environment
+  values: BTreeMap<String, String>,
+  /// Whether environment variables are used for the names not set explicitly
+  env: bool,
+}
+
+impl Variables {
+  /// Create a set of variables that only holds explicitly set values
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Create a set of variables that falls back to the environment
+  pub fn from_env() -> Self {
+    Self {
+      values: BTreeMap::new(),
+      env: true,
+    }
+  }
+
+  /// Set a variable, overriding the environment
+  pub fn set(&mut self, name: impl Into<String>, value: impl Into<String>) {
+    self.values.insert(name.into(), value.into());
+  }
+
+  /// Get the value of a variable
+  pub fn get(&self, name: &str) -> Option<String> {
+    self
+      .values
+      .get(name)
+      .cloned()
+      .or_else(|| self.env.then(|| env::var(name).ok()).flatten())
+  }
+
+  /// Replace the placeholders of a migration body starting at the given line of the file
+  pub fn substitute(&self, filename: &str, line: usize, text: &str) -> MidasResult<String> {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+      result.push_str(&rest[..start]);
+      let tail = &rest[start..];
+      let line = line + text[..text.len() - tail.len()].matches('\n').count();
+
+      if let Some(escaped) = tail.strip_prefix("$${") {
+        result.push_str("${");
+        rest = escaped;
+      } else if let Some(reference) = tail.strip_prefix("${") {
+        let name = reference
+          .find('}')
+          .map(|end| &reference[..end])
+          .filter(|name| is_valid_name(name))
+          .ok_or_else(|| MidasError::Parse {
+            filename: filename.to_owned(),
+            reason: format!("Invalid variable reference at line {line}, write $${{ for a literal ${{"),
+          })?;
+        let value = self.get(name).ok_or_else(|| MidasError::UndefinedVariable {
+          name: name.to_owned(),
+          filename: filename.to_owned(),
+          line,
+        })?;
+        result.push_str(&value);
+        rest = &reference[name.len() + 1..];
+      } else {
+        result.push('$');
+        rest = &tail[1..];
+      }
+    }
+    result.push_str(rest);
+    Ok(result)
+  }
+}
+
+/// Whether a variable name is made of letters, digits and underscores, not starting with a digit
+fn is_valid_name(name: &str) -> bool {
+  let mut chars = name.chars();
+  chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+    && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn it_should_substitute_variables() {
+    let mut variables = Variables::new();
+    variables.set("ROLE", "app_user");
+    variables.set("SCHEMA", "tenant_1");
+
+    let text = "GRANT SELECT ON ${SCHEMA}.users TO ${ROLE};\nSELECT '$${ROLE}', $1, $$body$$;";
+    assert_eq!(
+      variables.substitute("0000000000001_grant.sql", 2, text).unwrap(),
+      "GRANT SELECT ON tenant_1.users TO app_user;\nSELECT '${ROLE}', $1, $$body$$;"
+    );
+  }
+
+  #[test]
+  fn it_should_reject_undefined_and_invalid_variables() {
+    let variables = Variables::new();
+    let err = variables
+      .substitute("0000000000001_grant.sql", 2, "SELECT 1;\nGRANT ALL TO ${ROLE};")
+      .unwrap_err();
+    assert!(matches!(
+      err,
+      MidasError::UndefinedVariable { ref name, line: 3, .. } if name == "ROLE"
+    ));
+
+    assert!(matches!(
+      variables.substitute("0000000000001_grant.sql", 2, "SELECT '${not closed';"),
+      Err(MidasError::Parse { .. })
+    ));
+  }
+}
@@ -1,5 +1,10 @@
 use assert_cmd::Command;
-use assert_fs::prelude::PathChild as _;
+use assert_fs::prelude::{
+  FileWriteBin as _,
+  FileWriteStr as _,
+  PathChild as _,
+  PathCreateDir as _,
+};
 use assert_fs::TempDir;
 
 #[test]
@@ -142,3 +147,1117 @@ fn it_should_create_files_on_create_with_arg_with_space() -> anyhow::Result<()>
 
   Ok(())
 }
+
+#[test]
+fn it_should_refuse_to_run_when_applied_migration_was_edited() -> anyhow::Result<()> {
+  let temp_dir = TempDir::new()?;
+  let migration = temp_dir.child("migrations/0000000000001_create_users.sql");
+  migration
+    .write_str("-- !UP\nCREATE TABLE users (id INTEGER PRIMARY KEY);\n-- !DOWN\nDROP TABLE users;\n")?;
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "up"])
+    .current_dir(temp_dir.path());
+  cmd.assert().success();
+
+  migration.write_str(
+    "-- !UP\nCREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);\n-- !DOWN\nDROP TABLE users;\n",
+  )?;
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "up"])
+    .current_dir(temp_dir.path());
+  cmd
+    .assert()
+    .failure()
+    .stderr(predicates::str::contains("0000000000001_create_users.sql"));
+
+  Ok(())
+}
+
+#[test]
+fn it_should_show_history_of_applied_migrations() -> anyhow::Result<()> {
+  let temp_dir = TempDir::new()?;
+  temp_dir
+    .child("migrations/0000000000001_create_users.sql")
+    .write_str("-- !UP\nCREATE TABLE users (id INTEGER PRIMARY KEY);\n-- !DOWN\nDROP TABLE users;\n")?;
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "up"])
+    .current_dir(temp_dir.path());
+  cmd.assert().success();
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "history"])
+    .current_dir(temp_dir.path());
+  cmd
+    .assert()
+    .success()
+    .stdout(predicates::str::contains("0000000000001"))
+    .stdout(predicates::str::contains("0000000000001_create_users.sql"))
+    .stdout(predicates::str::contains(env!("CARGO_PKG_VERSION")))
+    .stdout(predicates::str::contains(" ms"));
+
+  Ok(())
+}
+
+#[test]
+fn it_should_upgrade_old_migrations_table_in_place() -> anyhow::Result<()> {
+  let temp_dir = TempDir::new()?;
+  temp_dir.child("migrations").create_dir_all()?;
+
+  let conn = rusqlite::Connection::open(temp_dir.child("data.db3").path())?;
+  conn.execute_batch(
+    "CREATE TABLE __schema_migrations (id INTEGER PRIMARY KEY AUTOINCREMENT, migration BIGINT);
+     INSERT INTO __schema_migrations (migration) VALUES (1567785996234);",
+  )?;
+  drop(conn);
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "history"])
+    .current_dir(temp_dir.path());
+  cmd
+    .assert()
+    .success()
+    .stdout(predicates::str::contains("1567785996234"));
+
+  Ok(())
+}
+
+#[test]
+fn it_should_fail_when_another_migrator_holds_the_lock() -> anyhow::Result<()> {
+  let temp_dir = TempDir::new()?;
+  temp_dir
+    .child("migrations/0000000000001_create_users.sql")
+    .write_str("-- !UP\nCREATE TABLE users (id INTEGER PRIMARY KEY);\n-- !DOWN\nDROP TABLE users;\n")?;
+  let lock_file = temp_dir.child(".migrations-state/data.db3.lock");
+  lock_file.write_str("1")?;
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "--lock-wait", "0", "up"])
+    .current_dir(temp_dir.path());
+  cmd
+    .assert()
+    .failure()
+    .stderr(predicates::str::contains("Another migrator holds the lock"));
+
+  // Another migrations table is locked separately
+  temp_dir
+    .child("billing/0000000000001_create_invoices.sql")
+    .write_str("-- !UP\nCREATE TABLE invoices (id INTEGER PRIMARY KEY);\n-- !DOWN\nDROP TABLE invoices;\n")?;
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args([
+      "--database",
+      "./data.db3",
+      "--source",
+      "./billing",
+      "--migrations-table",
+      "billing_migrations",
+      "--lock-wait",
+      "0",
+      "up",
+    ])
+    .current_dir(temp_dir.path());
+  cmd.assert().success();
+
+  std::fs::remove_file(lock_file.path())?;
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "up"])
+    .current_dir(temp_dir.path());
+  cmd.assert().success();
+  assert!(!lock_file.exists());
+
+  Ok(())
+}
+
+#[test]
+fn it_should_print_plan_without_applying_on_dry_run() -> anyhow::Result<()> {
+  let temp_dir = TempDir::new()?;
+  temp_dir
+    .child("migrations/0000000000001_create_users.sql")
+    .write_str("-- !UP\nCREATE TABLE users (id INTEGER PRIMARY KEY);\n-- !DOWN\nDROP TABLE users;\n")?;
+  temp_dir
+    .child("migrations/R_active_users.sql")
+    .write_str("CREATE VIEW IF NOT EXISTS active_users AS SELECT * FROM users;\n")?;
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "up", "--dry-run"])
+    .current_dir(temp_dir.path());
+  cmd
+    .assert()
+    .success()
+    .stdout(predicates::str::contains("0000000000001_create_users.sql"))
+    .stdout(predicates::str::contains(
+      "CREATE TABLE users (id INTEGER PRIMARY KEY);",
+    ))
+    .stdout(predicates::str::contains("R_active_users.sql"));
+
+  for subcommand in ["down", "revert"] {
+    let mut cmd = Command::cargo_bin("midas")?;
+    cmd
+      .args(["--database", "./data.db3", subcommand, "--dry-run"])
+      .current_dir(temp_dir.path());
+    cmd.assert().success();
+  }
+
+  // Dry runs leave the database untouched, the migrations tables are not created
+  let conn = rusqlite::Connection::open(temp_dir.child("data.db3").path())?;
+  let tables: i64 = conn.query_row("SELECT COUNT(*) FROM sqlite_master", (), |row| row.get(0))?;
+  assert_eq!(tables, 0);
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "status"])
+    .current_dir(temp_dir.path());
+  cmd
+    .assert()
+    .success()
+    .stdout(predicates::str::contains("Inactive"));
+
+  Ok(())
+}
+
+#[test]
+fn it_should_plan_a_dry_run_on_a_legacy_migrations_table() -> anyhow::Result<()> {
+  let temp_dir = TempDir::new()?;
+  temp_dir
+    .child("migrations/0000000000001_create_users.sql")
+    .write_str("-- !UP\nCREATE TABLE users (id INTEGER PRIMARY KEY);\n-- !DOWN\nDROP TABLE users;\n")?;
+  temp_dir
+    .child("migrations/0000000000002_create_posts.sql")
+    .write_str("-- !UP\nCREATE TABLE posts (id INTEGER PRIMARY KEY);\n-- !DOWN\nDROP TABLE posts;\n")?;
+
+  // The migrations table as created by the first releases, without checksums
+  let conn = rusqlite::Connection::open(temp_dir.child("data.db3").path())?;
+  conn.execute_batch(
+    "CREATE TABLE __schema_migrations (id INTEGER PRIMARY KEY AUTOINCREMENT, migration BIGINT);\n\
+     INSERT INTO __schema_migrations (migration) VALUES (1);",
+  )?;
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "up", "--dry-run"])
+    .current_dir(temp_dir.path());
+  cmd
+    .assert()
+    .success()
+    .stdout(predicates::str::contains("0000000000002_create_posts.sql"))
+    .stdout(predicates::boolean::PredicateBooleanExt::not(
+      predicates::str::contains("0000000000001_create_users.sql"),
+    ));
+
+  // The table is left as it was
+  let columns: i64 = conn.query_row(
+    "SELECT COUNT(*) FROM pragma_table_info('__schema_migrations')",
+    (),
+    |row| row.get(0),
+  )?;
+  assert_eq!(columns, 2);
+
+  Ok(())
+}
+
+#[test]
+fn it_should_run_every_statement_of_a_migration() -> anyhow::Result<()> {
+  let temp_dir = TempDir::new()?;
+  temp_dir
+    .child("migrations/0000000000001_create_tables.sql")
+    .write_str(
+      "-- !UP\nCREATE TABLE users (id INTEGER PRIMARY KEY);\nCREATE TABLE audits (user_id INTEGER);\n\
+     CREATE TRIGGER users_audit AFTER INSERT ON users BEGIN\n  INSERT INTO audits VALUES (NEW.id);\nEND;\n\
+     INSERT INTO users VALUES (1);\n-- !DOWN\nDROP TABLE audits;\nDROP TABLE users;\n",
+    )?;
+  temp_dir
+    .child("migrations/0000000000002_broken.sql")
+    .write_str("-- !UP\nCREATE TABLE a (id INTEGER);\nCREATE TABLE users (id INTEGER);\n-- !DOWN\n")?;
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "up"])
+    .current_dir(temp_dir.path());
+  cmd
+    .assert()
+    .failure()
+    .stderr(predicates::str::contains("statement 2 of 2"));
+
+  let conn = rusqlite::Connection::open(temp_dir.child("data.db3").path())?;
+  let audits: i64 = conn.query_row("SELECT COUNT(*) FROM audits", (), |row| row.get(0))?;
+  assert_eq!(audits, 1);
+
+  // The failed migration is rolled back as a whole
+  let tables: i64 = conn.query_row("SELECT COUNT(*) FROM sqlite_master WHERE name = 'a'", (), |row| {
+    row.get(0)
+  })?;
+  assert_eq!(tables, 0);
+
+  Ok(())
+}
+
+#[test]
+fn it_should_point_failed_statements_at_the_migration_file() -> anyhow::Result<()> {
+  let temp_dir = TempDir::new()?;
+  temp_dir
+    .child("migrations/0000000000001_broken.sql")
+    .write_str("-- !UP\nCREATE TABLE a (id INTEGER);\nINSERT INTO missing VALUES (1);\n-- !DOWN\n")?;
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "up"])
+    .current_dir(temp_dir.path());
+  cmd
+    .assert()
+    .failure()
+    .stderr(predicates::str::contains("error: no such table: missing"))
+    .stderr(predicates::str::contains("0000000000001_broken.sql:3:13"))
+    .stderr(predicates::str::contains("3 | INSERT INTO missing VALUES (1);"))
+    .stderr(predicates::str::contains("  |             ^"));
+
+  Ok(())
+}
+
+#[test]
+fn it_should_report_each_migration_as_json_or_plain_lines() -> anyhow::Result<()> {
+  let temp_dir = TempDir::new()?;
+  temp_dir
+    .child("migrations/0000000000001_create_users.sql")
+    .write_str("-- !UP\nCREATE TABLE users (id INTEGER PRIMARY KEY);\n-- !DOWN\nDROP TABLE users;\n")?;
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "--output", "json", "up"])
+    .current_dir(temp_dir.path());
+  let output = cmd.assert().success().get_output().stdout.clone();
+  let line = String::from_utf8(output)?;
+  let event: serde_json::Value = serde_json::from_str(line.trim())?;
+  assert_eq!(event["event"], "migration");
+  assert_eq!(event["status"], "applied");
+  assert_eq!(event["number"], 1);
+  assert_eq!(event["filename"], "0000000000001_create_users.sql");
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "--output", "plain", "revert"])
+    .current_dir(temp_dir.path());
+  cmd.assert().success().stdout(predicates::str::is_match(
+    r"^\d{4}-\d{2}-\d{2}T\S+Z reverted 0000000000001 0000000000001_create_users.sql \(\d+ ms\)\n$",
+  )?);
+
+  // Warnings are logged to stderr, every line on stdout stays a JSON event
+  temp_dir
+    .child("migrations/0000000000002_create_posts.sql")
+    .write_str("-- !UP\nCREATE TABLE posts (id INTEGER PRIMARY KEY);\n-- !DOWN\nDROP TABLE posts;\n")?;
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "up"])
+    .current_dir(temp_dir.path());
+  cmd.assert().success();
+  std::fs::remove_file(temp_dir.path().join("migrations/0000000000002_create_posts.sql"))?;
+  temp_dir
+    .child("migrations/0000000000003_create_tags.sql")
+    .write_str("-- !UP\nCREATE TABLE tags (id INTEGER PRIMARY KEY);\n-- !DOWN\nDROP TABLE tags;\n")?;
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args([
+      "--database",
+      "./data.db3",
+      "--output",
+      "json",
+      "--ignore-missing",
+      "up",
+    ])
+    .current_dir(temp_dir.path());
+  let assert = cmd
+    .assert()
+    .success()
+    .stderr(predicates::str::contains("missing on disk"));
+  let output = String::from_utf8(assert.get_output().stdout.clone())?;
+  assert!(!output.is_empty());
+  for line in output.lines() {
+    let event: serde_json::Value = serde_json::from_str(line)?;
+    assert_eq!(event["event"], "migration");
+  }
+
+  Ok(())
+}
+
+#[test]
+fn it_should_print_status_and_list_as_json_or_yaml() -> anyhow::Result<()> {
+  let temp_dir = TempDir::new()?;
+  temp_dir
+    .child("migrations/0000000000001_create_users.sql")
+    .write_str("-- !UP\nCREATE TABLE users (id INTEGER PRIMARY KEY);\n-- !DOWN\nDROP TABLE users;\n")?;
+  temp_dir
+    .child("migrations/R_active_users.sql")
+    .write_str("DROP VIEW IF EXISTS active_users;\nCREATE VIEW active_users AS SELECT id FROM users;\n")?;
+  temp_dir.child("migrations/R_user_count.sql").write_str(
+    "DROP VIEW IF EXISTS user_count;\nCREATE VIEW user_count AS SELECT COUNT(*) AS n FROM users;\n",
+  )?;
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "up"])
+    .current_dir(temp_dir.path());
+  cmd.assert().success();
+
+  temp_dir
+    .child("migrations/0000000000002_create_posts.sql")
+    .write_str("-- !UP\nCREATE TABLE posts (id INTEGER PRIMARY KEY);\n-- !DOWN\nDROP TABLE posts;\n")?;
+  temp_dir.child("migrations/R_active_users.sql").write_str(
+    "DROP VIEW IF EXISTS active_users;\nCREATE VIEW active_users AS SELECT id, 1 AS active FROM users;\n",
+  )?;
+  temp_dir
+    .child("migrations/R_recent_posts.sql")
+    .write_str("DROP VIEW IF EXISTS recent_posts;\nCREATE VIEW recent_posts AS SELECT id FROM posts;\n")?;
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "status", "--format", "json"])
+    .current_dir(temp_dir.path());
+  let output = cmd.assert().success().get_output().stdout.clone();
+  let status: serde_json::Value = serde_json::from_slice(&output)?;
+  let migrations = &status["migrations"];
+  assert_eq!(migrations[0]["name"], "create_users");
+  assert_eq!(migrations[0]["state"], "applied");
+  assert!(migrations[0]["applied_at"].is_string());
+  assert_eq!(migrations[1]["state"], "pending");
+  assert!(migrations[1]["applied_at"].is_null());
+
+  let repeatables = &status["repeatables"];
+  assert_eq!(repeatables[0]["name"], "active_users");
+  assert_eq!(repeatables[0]["filename"], "R_active_users.sql");
+  assert_eq!(repeatables[0]["state"], "outdated");
+  assert_eq!(repeatables[1]["name"], "recent_posts");
+  assert_eq!(repeatables[1]["state"], "pending");
+  assert!(repeatables[1]["applied_at"].is_null());
+  assert_eq!(repeatables[2]["name"], "user_count");
+  assert_eq!(repeatables[2]["state"], "applied");
+  assert!(repeatables[2]["applied_at"].is_string());
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "list", "--format", "yaml"])
+    .current_dir(temp_dir.path());
+  cmd.assert().success().stdout(predicates::str::contains(
+    "- number: 2\n  name: create_posts\n  filename: 0000000000002_create_posts.sql\n",
+  ));
+
+  Ok(())
+}
+
+#[test]
+fn it_should_report_and_refuse_orphaned_migrations() -> anyhow::Result<()> {
+  let temp_dir = TempDir::new()?;
+  temp_dir
+    .child("migrations/0000000000001_create_users.sql")
+    .write_str("-- !UP\nCREATE TABLE users (id INTEGER PRIMARY KEY);\n-- !DOWN\nDROP TABLE users;\n")?;
+  temp_dir
+    .child("migrations/0000000000002_create_posts.sql")
+    .write_str("-- !UP\nCREATE TABLE posts (id INTEGER PRIMARY KEY);\n-- !DOWN\nDROP TABLE posts;\n")?;
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "up"])
+    .current_dir(temp_dir.path());
+  cmd.assert().success();
+
+  std::fs::remove_file(temp_dir.path().join("migrations/0000000000002_create_posts.sql"))?;
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "status"])
+    .current_dir(temp_dir.path());
+  cmd
+    .assert()
+    .success()
+    .stdout(predicates::str::contains("Orphaned"));
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "down"])
+    .current_dir(temp_dir.path());
+  cmd
+    .assert()
+    .failure()
+    .stderr(predicates::str::contains("missing on disk: 0000000000002"));
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "down", "--ignore-missing"])
+    .current_dir(temp_dir.path());
+  cmd.assert().success();
+
+  let conn = rusqlite::Connection::open(temp_dir.path().join("data.db3"))?;
+  let remaining: Vec<i64> = conn
+    .prepare("SELECT migration FROM __schema_migrations ORDER BY id")?
+    .query_map([], |row| row.get(0))?
+    .collect::<Result<_, _>>()?;
+  assert_eq!(remaining, vec![2]);
+
+  Ok(())
+}
+
+#[test]
+fn it_should_validate_migrations_without_a_database() -> anyhow::Result<()> {
+  let temp_dir = TempDir::new()?;
+  temp_dir
+    .child("migrations/0000000000001_create_users.sql")
+    .write_str("-- !UP\nCREATE TABLE users (id INTEGER PRIMARY KEY);\n-- !DOWN\nDROP TABLE users;\n")?;
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd.args(["validate"]).current_dir(temp_dir.path());
+  cmd
+    .assert()
+    .success()
+    .stdout(predicates::str::contains("All 1 migration file(s) are valid."));
+
+  temp_dir
+    .child("migrations/0000000000002_broken.sql")
+    .write_str("-- !UP\n-- !DOWN\nDROP TABLE posts;\n")?;
+  temp_dir
+    .child("migrations/create_posts.sql")
+    .write_str("CREATE TABLE posts (id INTEGER);\n")?;
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd.args(["validate"]).current_dir(temp_dir.path());
+  cmd
+    .assert()
+    .failure()
+    .stdout(predicates::str::contains(
+      "0000000000002_broken.sql: the UP section is empty",
+    ))
+    .stdout(predicates::str::contains(
+      "create_posts.sql: filename does not match",
+    ))
+    .stdout(predicates::str::contains(
+      "create_posts.sql: missing the `-- !UP` marker",
+    ))
+    .stderr(predicates::str::contains(
+      "Found 4 problem(s) in 2 migration file(s)",
+    ));
+  assert!(!temp_dir.path().join("data.db3").exists());
+
+  Ok(())
+}
+
+#[test]
+fn it_should_lint_risky_statements_for_the_database_dialect() -> anyhow::Result<()> {
+  let temp_dir = TempDir::new()?;
+  temp_dir
+    .child("migrations/0000000000001_cleanup.sql")
+    .write_str("-- !UP\nCREATE INDEX users_idx ON users (id);\nDROP TABLE posts;\n-- !DOWN\n")?;
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd.args(["lint"]).current_dir(temp_dir.path());
+  cmd
+    .assert()
+    .success()
+    .stdout(predicates::str::contains(
+      "warning[drop-table]: 0000000000001_cleanup.sql:3:",
+    ))
+    .stdout(predicates::boolean::PredicateBooleanExt::not(
+      predicates::str::contains("create-index-non-concurrent"),
+    ));
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["lint", "--fail-on", "warning"])
+    .current_dir(temp_dir.path());
+  cmd.assert().failure().stderr(predicates::str::contains(
+    "Found 1 finding(s) at or above warning severity",
+  ));
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "postgres://localhost/app", "lint"])
+    .current_dir(temp_dir.path());
+  cmd.assert().failure().stdout(predicates::str::contains(
+    "error[create-index-non-concurrent]: 0000000000001_cleanup.sql:2:",
+  ));
+
+  temp_dir
+    .child("migrations/0000000000001_cleanup.sql")
+    .write_str("-- midas:allow drop-table\n-- !UP\nDROP TABLE posts;\n-- !DOWN\n")?;
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["lint", "--fail-on", "warning"])
+    .current_dir(temp_dir.path());
+  cmd.assert().success().stdout(predicates::str::contains(
+    "Linted 1 migration file(s), 0 finding(s).",
+  ));
+
+  Ok(())
+}
+
+#[test]
+fn it_should_create_files_from_templates() -> anyhow::Result<()> {
+  let temp_dir = TempDir::new()?;
+  temp_dir
+    .child("migrations/.templates/audit.sql")
+    .write_str("-- by {{author}}\n-- !UP\nCREATE TABLE {{table}}_audit (id INTEGER);\n-- !DOWN\nDROP TABLE {{table}}_audit;\n")?;
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["create", "create_users_table", "--template", "create_table"])
+    .current_dir(temp_dir.path());
+  cmd.assert().success();
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["create", "audit_posts", "--template", "audit", "--table", "posts"])
+    .current_dir(temp_dir.path());
+  cmd.assert().success();
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["create", "seed_users", "--template", "seed"])
+    .current_dir(temp_dir.path());
+  cmd
+    .assert()
+    .failure()
+    .stderr(predicates::str::contains("Template seed was not found"));
+
+  let read_migration = |suffix: &str| -> anyhow::Result<String> {
+    let entry = std::fs::read_dir(temp_dir.path().join("migrations"))?
+      .filter_map(Result::ok)
+      .find(|entry| entry.file_name().to_string_lossy().ends_with(suffix))
+      .ok_or_else(|| anyhow::anyhow!("migration file was not created"))?;
+    Ok(std::fs::read_to_string(entry.path())?)
+  };
+  assert!(read_migration("_create_users_table.sql")?
+    .contains("CREATE TABLE users (\n  id INTEGER PRIMARY KEY AUTOINCREMENT"));
+  let audit = read_migration("_audit_posts.sql")?;
+  assert!(audit.contains("CREATE TABLE posts_audit"));
+  assert!(!audit.contains("{{author}}"));
+  assert!(read_migration("_seed_users.sql").is_err());
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd.arg("up").current_dir(temp_dir.path());
+  cmd.assert().success();
+
+  Ok(())
+}
+
+#[test]
+fn it_should_create_and_apply_every_layout() -> anyhow::Result<()> {
+  let temp_dir = TempDir::new()?;
+
+  for (slug, layout) in [
+    ("create_users_table", "paired"),
+    ("create_posts_table", "directory"),
+  ] {
+    let mut cmd = Command::cargo_bin("midas")?;
+    cmd
+      .args(["create", slug, "--template", "create_table", "--layout", layout])
+      .current_dir(temp_dir.path());
+    cmd.assert().success();
+  }
+
+  let mut names = std::fs::read_dir(temp_dir.path().join("migrations"))?
+    .filter_map(Result::ok)
+    .map(|entry| {
+      entry
+        .file_name()
+        .to_string_lossy()
+        .split_once('_')
+        .unwrap()
+        .1
+        .to_owned()
+    })
+    .collect::<Vec<_>>();
+  names.sort();
+  assert_eq!(
+    names,
+    vec![
+      "create_posts_table",
+      "create_users_table.down.sql",
+      "create_users_table.up.sql",
+    ]
+  );
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "up"])
+    .current_dir(temp_dir.path());
+  cmd.assert().success();
+
+  let conn = rusqlite::Connection::open(temp_dir.path().join("data.db3"))?;
+  for table in ["users", "posts"] {
+    let count: i64 = conn.query_row(
+      "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?1",
+      [table],
+      |row| row.get(0),
+    )?;
+    assert_eq!(count, 1);
+  }
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "down"])
+    .current_dir(temp_dir.path());
+  cmd.assert().success();
+
+  Ok(())
+}
+
+#[test]
+fn it_should_number_migrations_sequentially() -> anyhow::Result<()> {
+  let temp_dir = TempDir::new()?;
+  temp_dir
+    .child("migrations/0009_create_users.sql")
+    .write_str("-- !UP\nCREATE TABLE users (id INTEGER PRIMARY KEY);\n-- !DOWN\nDROP TABLE users;\n")?;
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--version-format", "sequential", "create", "create_posts_table"])
+    .current_dir(temp_dir.path());
+  cmd.assert().success();
+  assert!(temp_dir
+    .path()
+    .join("migrations/0010_create_posts_table.sql")
+    .is_file());
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "status"])
+    .env("MIGRATIONS_VERSION_FORMAT", "sequential")
+    .current_dir(temp_dir.path());
+  cmd
+    .assert()
+    .success()
+    .stdout(predicates::str::contains("0009"))
+    .stdout(predicates::str::contains("0010"))
+    .stdout(predicates::boolean::PredicateBooleanExt::not(
+      predicates::str::contains("0000000000009"),
+    ));
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "status"])
+    .current_dir(temp_dir.path());
+  cmd.assert().success().stdout(predicates::str::contains(
+    "There are no available migration files.",
+  ));
+
+  // Orphaned migrations are reported in the same version format
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "--version-format", "sequential", "up"])
+    .current_dir(temp_dir.path());
+  cmd.assert().success();
+  std::fs::remove_file(temp_dir.path().join("migrations/0010_create_posts_table.sql"))?;
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args([
+      "--database",
+      "./data.db3",
+      "--version-format",
+      "sequential",
+      "down",
+    ])
+    .current_dir(temp_dir.path());
+  cmd
+    .assert()
+    .failure()
+    .stderr(predicates::str::contains("missing on disk: 0010."));
+
+  Ok(())
+}
+
+#[test]
+fn it_should_substitute_variables_in_migrations() -> anyhow::Result<()> {
+  let temp_dir = TempDir::new()?;
+  temp_dir
+    .child("migrations/0000000000001_create_users.sql")
+    .write_str(
+      "-- !UP\nCREATE TABLE ${PREFIX}_users (id INTEGER PRIMARY KEY, note TEXT DEFAULT '$${raw}');\n-- \
+     !DOWN\nDROP TABLE ${PREFIX}_users;\n",
+    )?;
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "up"])
+    .env_remove("PREFIX")
+    .current_dir(temp_dir.path());
+  cmd.assert().failure().stderr(predicates::str::contains(
+    "Variable ${PREFIX} used in 0000000000001_create_users.sql at line 2 is not defined",
+  ));
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args([
+      "--database",
+      "./data.db3",
+      "--dry-run",
+      "--var",
+      "PREFIX=cli",
+      "up",
+    ])
+    .env("PREFIX", "env")
+    .current_dir(temp_dir.path());
+  cmd
+    .assert()
+    .success()
+    .stdout(predicates::str::contains("CREATE TABLE cli_users"));
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "up"])
+    .env("PREFIX", "env")
+    .current_dir(temp_dir.path());
+  cmd.assert().success();
+
+  let conn = rusqlite::Connection::open(temp_dir.path().join("data.db3"))?;
+  conn.execute("INSERT INTO env_users (id) VALUES (1)", [])?;
+  let note: String = conn.query_row("SELECT note FROM env_users", [], |row| row.get(0))?;
+  assert_eq!(note, "${raw}");
+
+  Ok(())
+}
+
+#[test]
+fn it_should_reapply_repeatable_migrations_when_they_change() -> anyhow::Result<()> {
+  let temp_dir = TempDir::new()?;
+  temp_dir
+    .child("migrations/0000000000001_create_users.sql")
+    .write_str(
+      "-- !UP\nCREATE TABLE users (id INTEGER PRIMARY KEY, active INTEGER);\n-- !DOWN\nDROP TABLE users;\n",
+    )?;
+  temp_dir
+    .child("migrations/R_active_users.sql")
+    .write_str("DROP VIEW IF EXISTS active_users;\nCREATE VIEW active_users AS SELECT id FROM users;\n")?;
+
+  let run = |args: &[&str]| -> anyhow::Result<String> {
+    let mut cmd = Command::cargo_bin("midas")?;
+    cmd
+      .args(["--database", "./data.db3"])
+      .args(args)
+      .current_dir(temp_dir.path());
+    let output = cmd.assert().success().get_output().stdout.clone();
+    Ok(String::from_utf8(output)?)
+  };
+
+  let status = run(&["status"])?;
+  assert!(status.contains("Repeatable migrations"));
+  assert!(status.contains("R_active_users.sql"));
+
+  let plan = run(&["--dry-run", "up"])?;
+  assert!(plan.find("0000000000001_create_users.sql") < plan.find("R R_active_users.sql"));
+
+  run(&["up"])?;
+  assert!(run(&["up"])?.contains("Migrations are all up-to-date."));
+
+  temp_dir.child("migrations/R_active_users.sql").write_str(
+    "DROP VIEW IF EXISTS active_users;\nCREATE VIEW active_users AS SELECT id FROM users WHERE active = 1;\n",
+  )?;
+  assert!(run(&["status"])?.contains("Outdated"));
+  run(&["up"])?;
+
+  let conn = rusqlite::Connection::open(temp_dir.path().join("data.db3"))?;
+  conn.execute("INSERT INTO users (id, active) VALUES (1, 1), (2, 0)", [])?;
+  let count: i64 = conn.query_row("SELECT COUNT(*) FROM active_users", [], |row| row.get(0))?;
+  assert_eq!(count, 1);
+  let recorded: i64 = conn.query_row("SELECT COUNT(*) FROM __schema_repeatables", [], |row| row.get(0))?;
+  assert_eq!(recorded, 1);
+
+  Ok(())
+}
+
+#[test]
+fn it_should_run_callback_scripts_around_migrations() -> anyhow::Result<()> {
+  let temp_dir = TempDir::new()?;
+  temp_dir
+    .child("migrations/0000000000001_create_users.sql")
+    .write_str("-- !UP\nCREATE TABLE users (id INTEGER PRIMARY KEY);\n-- !DOWN\nDROP TABLE users;\n")?;
+  temp_dir
+    .child("migrations/0000000000002_create_posts.sql")
+    .write_str("-- !UP\nCREATE TABLE posts (id INTEGER PRIMARY KEY);\n-- !DOWN\nDROP TABLE posts;\n")?;
+  temp_dir.child("migrations/_before_all.sql").write_str(
+    "CREATE TABLE IF NOT EXISTS audit (event TEXT);\nINSERT INTO audit VALUES ('before_all');\n",
+  )?;
+  temp_dir
+    .child("migrations/_after_each.sql")
+    .write_str("INSERT INTO audit VALUES ('after_each');\n")?;
+  temp_dir
+    .child("migrations/_after_all.sql")
+    .write_str("ANALYZE;\nINSERT INTO audit VALUES ('after_all');\n")?;
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "--output", "plain", "up"])
+    .current_dir(temp_dir.path());
+  cmd
+    .assert()
+    .success()
+    .stdout(predicates::str::contains("callback _before_all.sql"))
+    .stdout(predicates::str::contains("callback _after_all.sql"));
+
+  let conn = rusqlite::Connection::open(temp_dir.path().join("data.db3"))?;
+  let mut stmt = conn.prepare("SELECT event FROM audit")?;
+  let events = stmt
+    .query_map([], |row| row.get::<_, String>(0))?
+    .collect::<Result<Vec<_>, _>>()?;
+  assert_eq!(
+    events,
+    vec!["before_all", "after_each", "after_each", "after_all"]
+  );
+
+  Ok(())
+}
+
+#[test]
+fn it_should_apply_timeouts_and_validate_timeout_directives() -> anyhow::Result<()> {
+  let temp_dir = TempDir::new()?;
+  temp_dir
+    .child("migrations/0000000000001_create_users.sql")
+    .write_str("-- !LOCK_TIMEOUT 1s\n-- !UP\nCREATE TABLE users (id INTEGER PRIMARY KEY);\n-- !DOWN\nDROP TABLE users;\n")?;
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args([
+      "--database",
+      "./data.db3",
+      "--lock-timeout",
+      "2s",
+      "--lock-retries",
+      "3",
+      "up",
+    ])
+    .current_dir(temp_dir.path());
+  cmd.assert().success();
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "--lock-timeout", "soon", "up"])
+    .current_dir(temp_dir.path());
+  cmd.assert().failure();
+
+  temp_dir
+    .child("migrations/0000000000002_create_posts.sql")
+    .write_str("-- !TIMEOUT forever\n-- !UP\nCREATE TABLE posts (id INTEGER PRIMARY KEY);\n-- !DOWN\nDROP TABLE posts;\n")?;
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "validate"])
+    .current_dir(temp_dir.path());
+  cmd.assert().failure().stdout(predicates::str::contains(
+    "0000000000002_create_posts.sql: Invalid duration in the -- !TIMEOUT directive",
+  ));
+
+  Ok(())
+}
+
+#[test]
+fn it_should_wait_until_the_database_is_reachable() -> anyhow::Result<()> {
+  let temp_dir = TempDir::new()?;
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "wait"])
+    .current_dir(temp_dir.path());
+  cmd
+    .assert()
+    .success()
+    .stdout(predicates::str::contains("The database is reachable"));
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args([
+      "--database",
+      "postgres://postgres@127.0.0.1:1/midas",
+      "--connect-timeout",
+      "1s",
+      "wait",
+      "--timeout",
+      "1s",
+    ])
+    .current_dir(temp_dir.path());
+  cmd
+    .assert()
+    .failure()
+    .stderr(predicates::str::contains("Database was not reachable within 1s"));
+
+  Ok(())
+}
+
+#[test]
+fn it_should_give_up_waiting_once_the_timeout_elapsed() -> anyhow::Result<()> {
+  let temp_dir = TempDir::new()?;
+
+  // The listener accepts connections but never answers, connection attempts then hang in the handshake
+  let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+  let database_url = format!(
+    "postgres://postgres@{}/midas?sslmode=disable",
+    listener.local_addr()?
+  );
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", &database_url, "wait", "--timeout", "1s"])
+    .current_dir(temp_dir.path())
+    .timeout(std::time::Duration::from_secs(30));
+  cmd
+    .assert()
+    .failure()
+    .stderr(predicates::str::contains("Database was not reachable within 1s"));
+
+  drop(listener);
+  Ok(())
+}
+
+#[test]
+fn it_should_record_migrations_in_the_configured_table() -> anyhow::Result<()> {
+  let temp_dir = TempDir::new()?;
+  temp_dir
+    .child("billing/0000000000001_create_invoices.sql")
+    .write_str("-- !UP\nCREATE TABLE invoices (id INTEGER PRIMARY KEY);\n-- !DOWN\nDROP TABLE invoices;\n")?;
+  temp_dir
+    .child("migrations/0000000000001_create_users.sql")
+    .write_str("-- !UP\nCREATE TABLE users (id INTEGER PRIMARY KEY);\n-- !DOWN\nDROP TABLE users;\n")?;
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args([
+      "--database",
+      "./data.db3",
+      "--source",
+      "billing",
+      "--migrations-table",
+      "billing_migrations",
+      "--migrations-schema",
+      "main",
+      "up",
+    ])
+    .current_dir(temp_dir.path());
+  cmd.assert().success();
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args(["--database", "./data.db3", "up"])
+    .current_dir(temp_dir.path());
+  cmd.assert().success();
+
+  let conn = rusqlite::Connection::open(temp_dir.path().join("data.db3"))?;
+  let count = |table: &str| -> rusqlite::Result<i64> {
+    conn.query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| row.get(0))
+  };
+  assert_eq!(count("billing_migrations")?, 1);
+  assert_eq!(count("__schema_migrations")?, 1);
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args([
+      "--database",
+      "./data.db3",
+      "--migrations-table",
+      "migrations; DROP TABLE users",
+      "status",
+    ])
+    .current_dir(temp_dir.path());
+  cmd
+    .assert()
+    .failure()
+    .stderr(predicates::str::contains("Invalid identifier"));
+
+  Ok(())
+}
+
+/// A self-signed CA certificate that signed none of the server certificates
+fn untrusted_ca() -> anyhow::Result<Vec<u8>> {
+  use openssl::asn1::Asn1Time;
+  use openssl::hash::MessageDigest;
+  use openssl::pkey::PKey;
+  use openssl::rsa::Rsa;
+  use openssl::x509::{
+    X509NameBuilder,
+    X509,
+  };
+
+  let key = PKey::from_rsa(Rsa::generate(2048)?)?;
+  let mut name = X509NameBuilder::new()?;
+  name.append_entry_by_text("CN", "untrusted CA")?;
+  let name = name.build();
+
+  let mut builder = X509::builder()?;
+  builder.set_version(2)?;
+  builder.set_subject_name(&name)?;
+  builder.set_issuer_name(&name)?;
+  builder.set_pubkey(&key)?;
+  builder.set_not_before(&*Asn1Time::days_from_now(0)?)?;
+  builder.set_not_after(&*Asn1Time::days_from_now(1)?)?;
+  builder.sign(&key, MessageDigest::sha256())?;
+  Ok(builder.build().to_pem()?)
+}
+
+/// Runs against a local Postgres serving a certificate signed by a locally generated CA, see the
+/// `gen-tls-certs` and `run-postgres-tls` tasks
+#[test]
+#[ignore = "needs MIDAS_TEST_POSTGRES_TLS_URL and MIDAS_TEST_POSTGRES_TLS_CA of a Postgres serving TLS"]
+fn it_should_connect_to_postgres_over_tls() -> anyhow::Result<()> {
+  let url = std::env::var("MIDAS_TEST_POSTGRES_TLS_URL")?;
+  let ca = std::fs::canonicalize(std::env::var("MIDAS_TEST_POSTGRES_TLS_CA")?)?;
+  let ca = ca
+    .to_str()
+    .ok_or_else(|| anyhow::anyhow!("CA path is not UTF-8"))?;
+  let temp_dir = TempDir::new()?;
+  temp_dir.child("untrusted.pem").write_binary(&untrusted_ca()?)?;
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args([
+      "--database",
+      &format!("{url}?sslmode=verify-full&sslrootcert={ca}"),
+      "wait",
+      "--timeout",
+      "5s",
+    ])
+    .current_dir(temp_dir.path());
+  cmd
+    .assert()
+    .success()
+    .stdout(predicates::str::contains("The database is reachable"));
+
+  // The flags take precedence over the parameters of the URL
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args([
+      "--database",
+      &format!("{url}?sslmode=verify-full&sslrootcert={ca}"),
+      "--sslrootcert",
+      "untrusted.pem",
+      "wait",
+      "--timeout",
+      "1s",
+    ])
+    .current_dir(temp_dir.path());
+  cmd
+    .assert()
+    .failure()
+    .stderr(predicates::str::contains("certificate verify failed"));
+
+  let mut cmd = Command::cargo_bin("midas")?;
+  cmd
+    .args([
+      "--database",
+      &url,
+      "--sslmode",
+      "verify-ca",
+      "--sslrootcert",
+      ca,
+      "wait",
+    ])
+    .current_dir(temp_dir.path());
+  cmd.assert().success();
+
+  Ok(())
+}